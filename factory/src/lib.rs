@@ -0,0 +1,338 @@
+use near_sdk::collections::{LazyOption, UnorderedMap};
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, Gas, NearToken, PanicOnDefault, Promise,
+};
+
+// Constants
+const NANOSECONDS: u64 = 1_000_000_000; // Nanoseconds to seconds
+const INIT_GAS: Gas = Gas::from_tgas(30); // Gas attached to the `new` call of a campaign
+const CALLBACK_GAS: Gas = Gas::from_tgas(10);
+const CAMPAIGN_STATE_BYTES: u128 = 10_000; // Storage reserved for the campaign state on top of its code
+
+/// Lifecycle of a deployed campaign
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum CampaignStatus {
+    Pending, // Deployment in progress
+    Active,  // Deployed and initialized
+    Failed,  // Deployment failed, the deposit was refunded
+    Closed,  // Marked as finished by the owner
+}
+
+/// Registry entry of a deployed campaign
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Campaign {
+    owner_id: AccountId,       // Owner of the campaign contract
+    token_contract: AccountId, // NEP-141 token staked in the campaign
+    total_reward: U128,        // Total reward passed to `new`
+    created_at: u64,           // Deployment timestamp in seconds
+    status: CampaignStatus,
+}
+
+/// Factory deploying publicai-staking campaigns as sub-accounts
+#[derive(PanicOnDefault)]
+#[near(contract_state)]
+pub struct StakingFactory {
+    owner_id: AccountId,                          // Factory owner
+    code: LazyOption<Vec<u8>>,                    // publicai-staking WASM
+    campaigns: UnorderedMap<AccountId, Campaign>, // Deployed campaigns
+}
+
+#[near]
+impl StakingFactory {
+    /// Initialize the factory
+    #[init]
+    pub fn new(owner_id: AccountId) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self {
+            owner_id,
+            code: LazyOption::new(b"c".to_vec(), None),
+            campaigns: UnorderedMap::new(b"m".to_vec()),
+        }
+    }
+
+    /// Store the publicai-staking WASM deployed to new campaigns (only callable by the owner).
+    /// The code is read directly from the input to avoid deserializing it.
+    pub fn set_code(&mut self) {
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set code."
+        );
+        let code = env::input().unwrap_or_else(|| env::panic_str("ERR_NO_INPUT"));
+        self.code.set(&code);
+        env::log_str(&format!("Code updated to {}", hex(&env::sha256(&code))));
+    }
+
+    /// Deploy a campaign to `<name>.<factory>` and call `new` on it (only callable by the owner).
+    /// The attached NEAR funds the campaign account and must cover its code and state storage.
+    /// - `name`: Sub-account name of the campaign.
+    /// - `owner_id`, `token_contract`, `total_reward`: Arguments of `new`. The campaign owner
+    ///   can schedule a later start with `set_stake_start_time`.
+    #[payable]
+    pub fn create_campaign(
+        &mut self,
+        name: String,
+        owner_id: AccountId,
+        token_contract: AccountId,
+        total_reward: U128,
+    ) -> Promise {
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can create campaign."
+        );
+        require!(!name.contains('.'), "Name cannot contain '.'");
+        let account_id: AccountId = format!("{}.{}", name, env::current_account_id())
+            .parse()
+            .unwrap_or_else(|_| env::panic_str("Invalid campaign name"));
+        require!(
+            self.campaigns
+                .get(&account_id)
+                .is_none_or(|campaign| campaign.status == CampaignStatus::Failed),
+            "Campaign already exists"
+        );
+        let code = self
+            .code
+            .get()
+            .unwrap_or_else(|| env::panic_str("Code is not set"));
+        let deposit = env::attached_deposit();
+        let min_deposit =
+            (code.len() as u128 + CAMPAIGN_STATE_BYTES) * env::storage_byte_cost().as_yoctonear();
+        assert!(
+            deposit.as_yoctonear() >= min_deposit,
+            "Attach at least {} yoctoNEAR",
+            min_deposit
+        );
+
+        self.campaigns.insert(
+            &account_id,
+            &Campaign {
+                owner_id: owner_id.clone(),
+                token_contract: token_contract.clone(),
+                total_reward,
+                created_at: env::block_timestamp() / NANOSECONDS,
+                status: CampaignStatus::Pending,
+            },
+        );
+        env::log_str(&format!("Deploy campaign {}", account_id));
+
+        Promise::new(account_id.clone())
+            .create_account()
+            .transfer(deposit)
+            .deploy_contract(code)
+            .function_call(
+                "new".to_string(),
+                serde_json::json!({
+                    "owner_id": owner_id,
+                    "token_contract": token_contract,
+                    "total_reward": total_reward,
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_near(0),
+                INIT_GAS,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(CALLBACK_GAS)
+                    .on_campaign_created(account_id, U128(deposit.as_yoctonear())),
+            )
+    }
+
+    /// Callback: After deployment, activate the campaign or refund the deposit.
+    #[private]
+    pub fn on_campaign_created(
+        &mut self,
+        account_id: AccountId,
+        deposit: U128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        let mut campaign = self.campaigns.get(&account_id).expect("Campaign not found");
+        let created = call_result.is_ok();
+        if created {
+            campaign.status = CampaignStatus::Active;
+        } else {
+            // The failed batch returned the deposit to the factory, pass it back to the owner
+            campaign.status = CampaignStatus::Failed;
+            Promise::new(self.owner_id.clone()).transfer(NearToken::from_yoctonear(deposit.0));
+        }
+        self.campaigns.insert(&account_id, &campaign);
+        env::log_str(&format!(
+            "Campaign {} status updated to {:?}",
+            account_id, campaign.status
+        ));
+        created
+    }
+
+    /// Mark a campaign as closed or active again (only callable by the owner).
+    #[payable]
+    pub fn set_campaign_status(&mut self, account_id: AccountId, status: CampaignStatus) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set campaign status."
+        );
+        let mut campaign = self.campaigns.get(&account_id).expect("Campaign not found");
+        require!(
+            matches!(
+                (&campaign.status, &status),
+                (CampaignStatus::Active, CampaignStatus::Closed)
+                    | (CampaignStatus::Closed, CampaignStatus::Active)
+            ),
+            "Only deployed campaigns can be closed or reopened"
+        );
+        campaign.status = status;
+        self.campaigns.insert(&account_id, &campaign);
+        env::log_str(&format!(
+            "Campaign {} status updated to {:?}",
+            account_id, campaign.status
+        ));
+    }
+
+    /// Query a campaign
+    pub fn get_campaign(&self, account_id: AccountId) -> Option<Campaign> {
+        self.campaigns.get(&account_id)
+    }
+
+    /// Query campaigns in deployment order
+    pub fn get_campaigns(
+        &self,
+        offset: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, Campaign)> {
+        let start = offset.unwrap_or(0);
+        let l = limit.unwrap_or(50);
+        self.campaigns
+            .iter()
+            .skip(start as usize)
+            .take(l as usize)
+            .collect()
+    }
+
+    /// Query the sha256 of the stored code, hex encoded
+    pub fn get_code_hash(&self) -> Option<String> {
+        self.code.get().map(|code| hex(&env::sha256(&code)))
+    }
+
+    /// Query owner
+    pub fn owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const TOKEN_CONTRACT: &str = "token.testnet";
+
+    fn get_context(predecessor: AccountId, attached_deposit: NearToken) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id("factory.testnet".parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .attached_deposit(attached_deposit);
+        builder
+    }
+
+    fn factory_with_code() -> StakingFactory {
+        let mut contract = StakingFactory::new(accounts(0));
+        contract.code.set(&vec![0u8; 100]);
+        contract
+    }
+
+    #[test]
+    fn test_create_campaign() {
+        let context = get_context(accounts(0), NearToken::from_near(5));
+        testing_env!(context.build());
+
+        let mut contract = factory_with_code();
+        contract.create_campaign(
+            "season1".to_string(),
+            accounts(1),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000),
+        );
+        let account_id: AccountId = "season1.factory.testnet".parse().unwrap();
+        let campaign = contract.get_campaign(account_id.clone()).unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Pending);
+        assert_eq!(campaign.owner_id, accounts(1));
+
+        assert!(contract.on_campaign_created(
+            account_id.clone(),
+            U128(NearToken::from_near(5).as_yoctonear()),
+            Ok(())
+        ));
+        assert_eq!(
+            contract.get_campaign(account_id.clone()).unwrap().status,
+            CampaignStatus::Active
+        );
+
+        let context = get_context(accounts(0), NearToken::from_yoctonear(1));
+        testing_env!(context.build());
+        contract.set_campaign_status(account_id.clone(), CampaignStatus::Closed);
+        let campaigns = contract.get_campaigns(None, None);
+        assert_eq!(campaigns.len(), 1);
+        assert_eq!(campaigns[0].1.status, CampaignStatus::Closed);
+    }
+
+    #[test]
+    fn test_failed_campaign_can_be_retried() {
+        let context = get_context(accounts(0), NearToken::from_near(5));
+        testing_env!(context.build());
+
+        let mut contract = factory_with_code();
+        let account_id: AccountId = "season1.factory.testnet".parse().unwrap();
+        contract.create_campaign(
+            "season1".to_string(),
+            accounts(1),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000),
+        );
+        assert!(!contract.on_campaign_created(
+            account_id.clone(),
+            U128(NearToken::from_near(5).as_yoctonear()),
+            Err(near_sdk::PromiseError::Failed)
+        ));
+        assert_eq!(
+            contract.get_campaign(account_id.clone()).unwrap().status,
+            CampaignStatus::Failed
+        );
+
+        contract.create_campaign(
+            "season1".to_string(),
+            accounts(1),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000),
+        );
+        assert_eq!(
+            contract.get_campaign(account_id).unwrap().status,
+            CampaignStatus::Pending
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach at least")]
+    fn test_create_campaign_requires_storage_deposit() {
+        let context = get_context(accounts(0), NearToken::from_yoctonear(1));
+        testing_env!(context.build());
+
+        let mut contract = factory_with_code();
+        contract.create_campaign(
+            "season1".to_string(),
+            accounts(1),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000),
+        );
+    }
+}
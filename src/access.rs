@@ -0,0 +1,253 @@
+use near_sdk::collections::UnorderedSet;
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::pool::{PoolId, DEFAULT_POOL_ID};
+use crate::storage::UnstakePayout;
+use crate::{StakingContract, StakingContractExt};
+
+/// Who can stake, shared by all pools
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessMode {
+    Open,      // Any account not on the denylist
+    Allowlist, // Only allowlisted accounts not on the denylist
+}
+
+#[near]
+impl StakingContract {
+    /// Set the access mode (only callable by the owner).
+    /// Existing positions are kept when an account loses access.
+    #[payable]
+    pub fn set_access_mode(&mut self, mode: AccessMode) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set access mode."
+        );
+        self.access_mode = mode;
+        env::log_str(&format!("Access mode updated to {:?}", self.access_mode));
+    }
+
+    /// Allow accounts to stake in allowlist mode (only callable by the owner).
+    #[payable]
+    pub fn add_to_allowlist(&mut self, account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can update allowlist."
+        );
+        for account_id in account_ids {
+            self.allowlist.insert(&account_id);
+            env::log_str(&format!("Add {} to allowlist", account_id));
+        }
+    }
+
+    /// Remove accounts from the allowlist (only callable by the owner).
+    #[payable]
+    pub fn remove_from_allowlist(&mut self, account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can update allowlist."
+        );
+        for account_id in account_ids {
+            self.allowlist.remove(&account_id);
+            env::log_str(&format!("Remove {} from allowlist", account_id));
+        }
+    }
+
+    /// Block accounts from staking and claiming rewards, principal can still be withdrawn.
+    /// Rewards they would be paid are frozen until they are removed from the denylist.
+    /// The owner or the guardian can add accounts, only the owner can remove them.
+    #[payable]
+    pub fn add_to_denylist(&mut self, account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == self.owner_id || self.guardian_id.as_ref() == Some(&caller),
+            "Only the owner or guardian can update denylist."
+        );
+        for account_id in account_ids {
+            self.denylist.insert(&account_id);
+            env::log_str(&format!("Add {} to denylist", account_id));
+        }
+    }
+
+    /// Remove accounts from the denylist (only callable by the owner).
+    /// Their frozen rewards are paid into their pending payouts, see `withdraw_pending`.
+    #[payable]
+    pub fn remove_from_denylist(&mut self, account_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can remove from denylist."
+        );
+        for account_id in account_ids {
+            self.denylist.remove(&account_id);
+            env::log_str(&format!("Remove {} from denylist", account_id));
+            self.release_frozen_rewards(&account_id);
+        }
+    }
+
+    /// Query access mode
+    pub fn get_access_mode(&self) -> AccessMode {
+        self.access_mode.clone()
+    }
+
+    /// Query whether an account is on the allowlist
+    pub fn is_allowlisted(&self, account_id: AccountId) -> bool {
+        self.allowlist.contains(&account_id)
+    }
+
+    /// Query whether an account is on the denylist
+    pub fn is_denylisted(&self, account_id: AccountId) -> bool {
+        self.denylist.contains(&account_id)
+    }
+
+    /// Query allowlisted accounts
+    pub fn get_allowlist(&self, offset: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        page(&self.allowlist, offset, limit)
+    }
+
+    /// Query denylisted accounts
+    pub fn get_denylist(&self, offset: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        page(&self.denylist, offset, limit)
+    }
+
+    /// Release the reward frozen for the caller in a pool into its pending payouts, once
+    /// claims are resumed. Rewards frozen by the denylist are released by the owner.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn withdraw_frozen_reward(&mut self, pool_id: Option<PoolId>) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        require!(
+            !self.denylist.contains(&account_id),
+            "Account is denylisted"
+        );
+        require!(!self.load_pool(pool_id).pause_status.claim, "Claim paused");
+        require!(
+            self.frozen_rewards
+                .contains_key(&(pool_id, account_id.clone())),
+            "No frozen reward"
+        );
+        U128(self.release_frozen_reward(pool_id, &account_id))
+    }
+
+    /// Query the reward frozen for an account in a pool, by the denylist or a claim pause
+    pub fn get_frozen_reward(&self, account_id: AccountId, pool_id: Option<PoolId>) -> u128 {
+        self.frozen_rewards
+            .get(&(pool_id.unwrap_or(DEFAULT_POOL_ID), account_id))
+            .unwrap_or(0)
+    }
+}
+
+impl StakingContract {
+    /// Whether an account can open or add to a position.
+    pub(crate) fn can_stake(&self, account_id: &AccountId) -> bool {
+        !self.denylist.contains(account_id)
+            && (self.access_mode == AccessMode::Open || self.allowlist.contains(account_id))
+    }
+
+    /// Keep the reward owed to an account that left its position without being paid,
+    /// because it is denylisted or claims are paused.
+    /// It stays in the liability of the pool until it is paid.
+    pub(crate) fn freeze_reward(&mut self, pool_id: PoolId, account_id: &AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        let key = (pool_id, account_id.clone());
+        let frozen = self.frozen_rewards.get(&key).unwrap_or(0);
+        self.frozen_rewards.insert(&key, &(frozen + amount));
+        env::log_str(&format!(
+            "Reward of {} for {} frozen in pool {}",
+            amount, account_id, pool_id
+        ));
+    }
+
+    /// Undo `freeze_reward` when the unstake that froze the reward is rejected.
+    /// Returns the amount unfrozen, less than `amount` if it was released in the meantime.
+    pub(crate) fn unfreeze_reward(
+        &mut self,
+        pool_id: PoolId,
+        account_id: &AccountId,
+        amount: u128,
+    ) -> u128 {
+        if amount == 0 {
+            return 0;
+        }
+        let key = (pool_id, account_id.clone());
+        let frozen = self.frozen_rewards.get(&key).unwrap_or(0);
+        let amount = std::cmp::min(amount, frozen);
+        let frozen = frozen - amount;
+        if frozen == 0 {
+            self.frozen_rewards.remove(&key);
+        } else {
+            self.frozen_rewards.insert(&key, &frozen);
+        }
+        amount
+    }
+
+    /// Pay the frozen rewards of an account into its pending payouts in every pool where
+    /// claims are not paused.
+    fn release_frozen_rewards(&mut self, account_id: &AccountId) {
+        let pool_ids: Vec<_> = self.pools.keys().collect();
+        for pool_id in pool_ids {
+            if !self.load_pool(pool_id).pause_status.claim {
+                self.release_frozen_reward(pool_id, account_id);
+            }
+        }
+    }
+
+    /// Pay the frozen reward of an account in a pool into its pending payouts, with the
+    /// haircut, protocol fee, vesting and referral rules of `unstake`. Returns the reward paid.
+    fn release_frozen_reward(&mut self, pool_id: PoolId, account_id: &AccountId) -> u128 {
+        let key = (pool_id, account_id.clone());
+        let Some(owed_reward) = self.frozen_rewards.remove(&key) else {
+            return 0;
+        };
+        let mut pool = self.load_pool(pool_id);
+        pool.update_liability();
+        let reward = pool.apply_haircut(owed_reward);
+        pool.release_liability(owed_reward);
+        let fee = self.protocol_fee(reward);
+        let vested = if pool.vesting.is_some() {
+            reward - fee
+        } else {
+            0
+        };
+        let payout = UnstakePayout {
+            principal: 0,
+            withdrawn: owed_reward,
+            reward,
+            fee,
+            vested,
+            frozen: 0,
+            total: reward - fee - vested,
+        };
+        pool.total_claimed_reward += reward;
+        self.record_earned_reward(pool_id, account_id, reward);
+        self.complete_payout(pool_id, &mut pool, account_id, &payout);
+        self.save_pool(pool_id, &pool);
+        if payout.total > 0 {
+            self.add_pending_payout(pool_id, account_id, payout.total);
+        }
+        env::log_str(&format!(
+            "Frozen reward of {} for {} released in pool {}",
+            reward, account_id, pool_id
+        ));
+        reward
+    }
+}
+
+fn page(set: &UnorderedSet<AccountId>, offset: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+    let start = offset.unwrap_or(0);
+    let l = limit.unwrap_or(50);
+    set.iter().skip(start as usize).take(l as usize).collect()
+}
@@ -0,0 +1,277 @@
+use near_sdk::json_types::{Base58CryptoHash, U128};
+use near_sdk::{assert_one_yocto, env, near, require, CryptoHash};
+
+use crate::pool::{PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, UserOperationState};
+
+/// Leaves tracked by one word of the claimed bitmap
+const LEAVES_PER_WORD: u64 = 128;
+
+/// Where an airdropped bonus goes
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AirdropTarget {
+    Reward, // Added to the accumulated reward of the position
+    Stake,  // Added to the principal, opening a position if needed
+}
+
+/// Bonus allocations committed to by a merkle root
+#[near(serializers = [borsh])]
+pub struct Airdrop {
+    pool_id: PoolId,
+    merkle_root: CryptoHash, // Root of the (index, account, amount) leaves
+    target: AirdropTarget,
+    total: u128,   // Reserved from the reward budget of the pool
+    claimed: u128, // Claimed so far
+    closed: bool,  // Closed airdrops cannot be claimed
+}
+
+/// Airdrop settings and totals
+#[near(serializers = [json])]
+pub struct AirdropView {
+    airdrop_id: u64,
+    pool_id: PoolId,
+    merkle_root: Base58CryptoHash,
+    target: AirdropTarget,
+    total: U128,
+    claimed: U128,
+    closed: bool,
+}
+
+#[near]
+impl StakingContract {
+    /// Create an airdrop of bonus rewards (only callable by the owner).
+    /// Each leaf is `sha256(borsh((index: u64, account_id: AccountId, amount: u128)))`,
+    /// each node hashes its two children in ascending order.
+    /// - `merkle_root`: Root of the allocations.
+    /// - `total`: Sum of the allocations, moved from the reward budget of the pool.
+    /// - `target`: Add bonuses to the accumulated reward or to the principal.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn create_airdrop(
+        &mut self,
+        merkle_root: Base58CryptoHash,
+        total: U128,
+        target: AirdropTarget,
+        pool_id: Option<PoolId>,
+    ) -> u64 {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can create airdrop."
+        );
+        require!(total.0 > 0, "Airdrop total should be positive");
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(!pool.finalized, "Pool is finalized");
+        pool.update_liability();
+        // Rewards already accrued by stakers keep their share of the budget
+        let available = pool
+            .remaining_reward_budget()
+            .saturating_sub(pool.accrued_reward_liability);
+        require!(
+            total.0 <= available,
+            "Airdrop total exceeds the reward budget"
+        );
+        pool.total_reward -= total.0;
+        pool.airdrop_reserve += total.0;
+        self.save_pool(pool_id, &pool);
+
+        let airdrop_id = self.airdrops.len();
+        self.airdrops.insert(
+            &airdrop_id,
+            &Airdrop {
+                pool_id,
+                merkle_root: merkle_root.into(),
+                target,
+                total: total.0,
+                claimed: 0,
+                closed: false,
+            },
+        );
+        env::log_str(&format!(
+            "Airdrop {} of {} created in pool {}",
+            airdrop_id, total.0, pool_id
+        ));
+        airdrop_id
+    }
+
+    /// Close an airdrop and return its unclaimed amount to the reward budget
+    /// (only callable by the owner).
+    #[payable]
+    pub fn close_airdrop(&mut self, airdrop_id: u64) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can close airdrop."
+        );
+        let mut airdrop = self.airdrops.get(&airdrop_id).expect("Airdrop not found");
+        require!(!airdrop.closed, "Airdrop already closed");
+        airdrop.closed = true;
+        self.airdrops.insert(&airdrop_id, &airdrop);
+        let unclaimed = airdrop.total - airdrop.claimed;
+        let mut pool = self.load_pool(airdrop.pool_id);
+        pool.airdrop_reserve -= unclaimed;
+        pool.total_reward += unclaimed;
+        self.save_pool(airdrop.pool_id, &pool);
+        env::log_str(&format!(
+            "Airdrop {} closed, {} returned to the reward budget",
+            airdrop_id, unclaimed
+        ));
+    }
+
+    /// Claim the bonus allocated to the caller.
+    /// - `index`: Index of the leaf.
+    /// - `amount`: Allocated amount.
+    /// - `proof`: Sibling hashes from the leaf up to the root.
+    #[payable]
+    pub fn claim_airdrop(
+        &mut self,
+        airdrop_id: u64,
+        index: u64,
+        amount: U128,
+        proof: Vec<Base58CryptoHash>,
+    ) {
+        assert_one_yocto();
+        require!(!self.emergency_mode, "Emergency mode enabled");
+        let account_id = env::predecessor_account_id();
+        require!(
+            !self.denylist.contains(&account_id),
+            "Account is denylisted"
+        );
+        let mut airdrop = self.airdrops.get(&airdrop_id).expect("Airdrop not found");
+        require!(!airdrop.closed, "Airdrop closed");
+        require!(
+            !self.is_airdrop_claimed(airdrop_id, index),
+            "Airdrop already claimed"
+        );
+        let leaf =
+            env::sha256_array(&near_sdk::borsh::to_vec(&(index, &account_id, amount.0)).unwrap());
+        require!(
+            verify_proof(leaf, &proof, airdrop.merkle_root),
+            "Invalid merkle proof"
+        );
+        let in_progress = matches!(
+            self.user_states.get(&account_id),
+            Some(UserOperationState::Staking | UserOperationState::Unstaking)
+        );
+        require!(
+            !in_progress || self.is_user_state_stale(&account_id),
+            "Operation already in progress."
+        );
+
+        require!(
+            amount.0 <= airdrop.total - airdrop.claimed,
+            "Airdrop total exceeded"
+        );
+
+        let pool_id = airdrop.pool_id;
+        self.assert_liability_counted(pool_id);
+        let mut pool = self.load_pool(pool_id);
+        pool.airdrop_reserve -= amount.0;
+        let mut pending = 0;
+        match airdrop.target {
+            AirdropTarget::Reward => {
+                let mut stake_info = pool
+                    .stakes
+                    .get(&account_id)
+                    .expect("No stake found for this account");
+                // The bonus goes back into the budget and is owed like any accrued reward
+                stake_info.accumulated_reward += amount.0;
+                pool.stakes.insert(&account_id, &stake_info);
+                pool.total_reward += amount.0;
+                pool.update_liability();
+                pool.accrued_reward_liability += amount.0;
+            }
+            AirdropTarget::Stake => {
+                // Admitted like a transfer to `ft_on_transfer`, the rest is owed as a pending payout
+                let staked = pool
+                    .stakes
+                    .get(&account_id)
+                    .map(|stake_info| stake_info.amount)
+                    .unwrap_or(0);
+                let accepted = if self.can_stake(&account_id) && !pool.pause_status.stake {
+                    pool.admitted_stake(staked, amount.0).0
+                } else {
+                    0
+                };
+                if accepted > 0 {
+                    self.add_stake(pool_id, &mut pool, &account_id, accepted);
+                }
+                pending = amount.0 - accepted;
+            }
+        }
+        self.save_pool(pool_id, &pool);
+        if pending > 0 {
+            self.add_pending_payout(pool_id, &account_id, pending);
+            env::log_str(&format!(
+                "Airdrop of {} to {} cannot be staked, recorded as pending",
+                pending, account_id
+            ));
+        }
+
+        airdrop.claimed += amount.0;
+        self.airdrops.insert(&airdrop_id, &airdrop);
+        let word = index / LEAVES_PER_WORD;
+        let bits = self.airdrop_claims.get(&(airdrop_id, word)).unwrap_or(0);
+        self.airdrop_claims.insert(
+            &(airdrop_id, word),
+            &(bits | 1 << (index % LEAVES_PER_WORD)),
+        );
+        env::log_str(&format!(
+            "Airdrop {} of {} claimed by {}",
+            airdrop_id, amount.0, account_id
+        ));
+    }
+
+    /// Query an airdrop
+    pub fn get_airdrop(&self, airdrop_id: u64) -> Option<AirdropView> {
+        self.airdrops.get(&airdrop_id).map(|airdrop| AirdropView {
+            airdrop_id,
+            pool_id: airdrop.pool_id,
+            merkle_root: airdrop.merkle_root.into(),
+            target: airdrop.target,
+            total: U128(airdrop.total),
+            claimed: U128(airdrop.claimed),
+            closed: airdrop.closed,
+        })
+    }
+
+    /// Query whether the leaf at `index` of an airdrop is claimed
+    pub fn is_airdrop_claimed(&self, airdrop_id: u64, index: u64) -> bool {
+        self.airdrop_claims
+            .get(&(airdrop_id, index / LEAVES_PER_WORD))
+            .is_some_and(|bits| bits & 1 << (index % LEAVES_PER_WORD) != 0)
+    }
+}
+
+impl StakingContract {
+    /// Close the open airdrops of a pool, the caller releases their reserve.
+    pub(crate) fn close_pool_airdrops(&mut self, pool_id: PoolId) {
+        let open: Vec<(u64, Airdrop)> = self
+            .airdrops
+            .iter()
+            .filter(|(_, airdrop)| airdrop.pool_id == pool_id && !airdrop.closed)
+            .collect();
+        for (airdrop_id, mut airdrop) in open {
+            airdrop.closed = true;
+            self.airdrops.insert(&airdrop_id, &airdrop);
+        }
+    }
+}
+
+/// Hash `leaf` up the tree with `proof` and compare with `root`.
+fn verify_proof(leaf: CryptoHash, proof: &[Base58CryptoHash], root: CryptoHash) -> bool {
+    let node = proof.iter().fold(leaf, |node, sibling| {
+        let sibling: CryptoHash = (*sibling).into();
+        let (left, right) = if node <= sibling {
+            (node, sibling)
+        } else {
+            (sibling, node)
+        };
+        env::sha256_array(&[left, right].concat())
+    });
+    node == root
+}
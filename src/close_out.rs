@@ -0,0 +1,136 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, Gas, NearToken, Promise, PromiseOrValue};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, NANOSECONDS, WEEK};
+
+/// Time after the end of a pool after which it can be finalized with positions still open
+pub const CLOSE_OUT_GRACE_PERIOD: u64 = 4 * WEEK;
+
+/// Close-out state of a pool
+#[near(serializers = [json])]
+pub struct CloseOut {
+    finalized: bool,
+    open_positions: u64, // Positions still in the pool
+    finalizable_at: u64, // Earliest time to finalize with positions still open, 0 without end time
+    unused_reward: U128, // Swept when finalizing, 0 once finalized
+    swept_reward: U128,  // Swept when the pool was finalized
+}
+
+#[near]
+impl StakingContract {
+    /// Finalize a pool after its end time and sweep its unused reward to the treasury, or to
+    /// the owner when no treasury is set (only callable by the owner). Every position must be
+    /// settled unless CLOSE_OUT_GRACE_PERIOD has passed since the end time. Rewards still owed
+    /// stay payable, the pool no longer takes stakes, reward budget or airdrops.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn finalize_pool(&mut self, pool_id: Option<PoolId>) -> PromiseOrValue<bool> {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can finalize pool."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(!pool.finalized, "Pool is finalized");
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        require!(pool.has_ended(current_time), "Pool has not ended");
+        require!(
+            pool.stakes.is_empty() || current_time >= pool.stake_end_time + CLOSE_OUT_GRACE_PERIOD,
+            "Positions are still open"
+        );
+
+        // Lock the accounting, the budget only covers rewards already owed from now on
+        pool.update_liability();
+        let unused = pool.unused_reward();
+        self.close_pool_airdrops(pool_id);
+        pool.total_reward -= unused - pool.airdrop_reserve - pool.referral_budget;
+        pool.airdrop_reserve = 0;
+        pool.referral_budget = 0;
+        pool.finalized = true;
+        pool.swept_reward = unused;
+        self.save_pool(pool_id, &pool);
+        let receiver_id = self.treasury_id.clone().unwrap_or(self.owner_id.clone());
+        env::log_str(&format!(
+            "Pool {} finalized, sweep {} to {}",
+            pool_id, unused, receiver_id
+        ));
+
+        if unused == 0 {
+            return PromiseOrValue::Value(true);
+        }
+        PromiseOrValue::Promise(
+            Promise::new(pool.staked_token)
+                .function_call(
+                    "ft_transfer".to_string(),
+                    serde_json::json!({
+                        "receiver_id": receiver_id,
+                        "amount": unused.to_string(),
+                    })
+                    .to_string()
+                    .into_bytes(),
+                    NearToken::from_yoctonear(1),
+                    Gas::from_gas(20_000_000_000_000),
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                        .on_reward_swept(pool_id, unused),
+                ),
+        )
+    }
+
+    /// Callback: After ft_transfer, report a failed sweep. The tokens are no longer
+    /// reserved and can be recovered with `withdraw_token`.
+    #[private]
+    pub fn on_reward_swept(
+        &mut self,
+        pool_id: PoolId,
+        amount: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                env::log_str(&format!(
+                    "Sweep of {} from pool {} failed, use withdraw_token",
+                    amount, pool_id
+                ));
+                false
+            }
+        }
+    }
+
+    /// Query the close-out state of a pool
+    pub fn get_close_out(&self, pool_id: Option<PoolId>) -> CloseOut {
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let pool = self.load_pool(pool_id);
+        CloseOut {
+            finalized: pool.finalized,
+            open_positions: pool.stakes.len(),
+            finalizable_at: if pool.stake_end_time == 0 {
+                0
+            } else {
+                pool.stake_end_time + CLOSE_OUT_GRACE_PERIOD
+            },
+            unused_reward: U128(if pool.finalized {
+                0
+            } else {
+                pool.unused_reward()
+            }),
+            swept_reward: U128(pool.swept_reward),
+        }
+    }
+}
+
+impl Pool {
+    /// Reward budget no longer needed by the pool:
+    /// `total_reward - total_claimed_reward - outstanding`, plus unclaimed airdrops and the
+    /// referral budget. Outstanding rewards are those accrued by open positions.
+    fn unused_reward(&self) -> u128 {
+        let outstanding = std::cmp::min(self.current_liability(), self.remaining_reward_budget());
+        self.remaining_reward_budget() - outstanding + self.airdrop_reserve + self.referral_budget
+    }
+}
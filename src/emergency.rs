@@ -0,0 +1,118 @@
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, Gas, NearToken, Promise};
+
+use crate::pool::{PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt};
+
+#[near]
+impl StakingContract {
+    /// Set the guardian account (only callable by the owner).
+    /// - `guardian_id`: Account allowed to trigger emergency mode, `None` removes it.
+    #[payable]
+    pub fn set_guardian(&mut self, guardian_id: Option<AccountId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set guardian."
+        );
+        self.guardian_id = guardian_id;
+        env::log_str(&format!("Guardian updated to {:?}", self.guardian_id));
+    }
+
+    /// Enable or disable emergency mode.
+    /// The owner or the guardian can enable it, only the owner can disable it.
+    #[payable]
+    pub fn set_emergency_mode(&mut self, enabled: bool) {
+        assert_one_yocto();
+        let caller = env::predecessor_account_id();
+        let is_guardian = self.guardian_id.as_ref() == Some(&caller);
+        if enabled {
+            require!(
+                caller == self.owner_id || is_guardian,
+                "Only the owner or guardian can enable emergency mode."
+            );
+        } else {
+            require!(
+                caller == self.owner_id,
+                "Only the owner can disable emergency mode."
+            );
+        }
+        self.emergency_mode = enabled;
+        env::log_str(&format!(
+            "Emergency mode updated to {}",
+            self.emergency_mode
+        ));
+    }
+
+    /// Withdraw the staked principal while emergency mode is enabled.
+    /// Rewards are forfeited, lock, pause and operation state checks are skipped.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn emergency_withdraw(&mut self, pool_id: Option<PoolId>) -> Promise {
+        assert_one_yocto();
+        require!(self.emergency_mode, "Emergency mode is not enabled");
+        let account_id = env::predecessor_account_id();
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        let stake_info = pool
+            .stakes
+            .remove(&account_id)
+            .expect("No stake found for this account");
+        // No reward math on the way out, a broken accumulator or schedule cannot block it.
+        // Partner rewards, loyalty and the haircut are left as they are.
+        self.count_leaving_position(pool_id, &mut pool, &account_id, &stake_info);
+        pool.write_off_position(&stake_info);
+        self.track_position(pool_id, &account_id, 0);
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Emergency withdraw of {} for {}",
+            stake_info.amount, account_id
+        ));
+
+        Promise::new(pool.staked_token)
+            .function_call(
+                "ft_transfer".to_string(),
+                serde_json::json!({
+                    "receiver_id": account_id,
+                    "amount": stake_info.amount.to_string(),
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_gas(20_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                    .on_emergency_withdraw(pool_id, account_id, stake_info.amount),
+            )
+    }
+
+    /// Callback: After the emergency ft_transfer, record the principal as pending if it failed.
+    #[private]
+    pub fn on_emergency_withdraw(
+        &mut self,
+        pool_id: PoolId,
+        account_id: AccountId,
+        amount: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                self.credit_pending_payout(pool_id, &account_id, amount);
+                false
+            }
+        }
+    }
+
+    /// Query whether emergency mode is enabled
+    pub fn is_emergency_mode(&self) -> bool {
+        self.emergency_mode
+    }
+
+    /// Query guardian
+    pub fn get_guardian(&self) -> Option<AccountId> {
+        self.guardian_id.clone()
+    }
+}
@@ -0,0 +1,52 @@
+use near_sdk::json_types::U128;
+use near_sdk::{near, AccountId, PromiseOrValue};
+
+use crate::pool::PoolId;
+
+/// Why `ft_on_transfer` returned tokens to the sender
+#[near(serializers = [json])]
+#[serde(rename_all = "snake_case")]
+pub enum RefundReason {
+    InvalidMsg,          // msg is not a valid TransferMessage
+    PoolNotFound,        // msg selects a pool that does not exist
+    WrongToken,          // Token is neither staked nor a reward token of the pool
+    AccessDenied,        // Sender is not allowlisted or is denylisted
+    StakePaused,         // Staking is paused in the pool
+    EmergencyMode,       // Emergency mode is enabled
+    OperationInProgress, // Sender has a stake or unstake in progress
+    StakeCapReached,     // Part of the amount is above the stake caps
+    BelowMinStake,       // Position would be below the minimum stake
+    Unauthorized,        // Only the owner can seed positions
+    InvalidSeed,         // A seeded position is empty, in the future or already exists
+    SeedTotalMismatch,   // Seeded principal and rewards do not add up to the amount
+    PoolFinalized,       // The pool has been closed out
+}
+
+/// NEP-297 events of the staking contract
+#[near(event_json(standard = "publicai-staking"))]
+pub enum StakingEvent {
+    #[event_version("1.0.0")]
+    StakeRefunded {
+        sender_id: AccountId,
+        pool_id: Option<PoolId>,
+        amount: U128,
+        reason: RefundReason,
+    },
+}
+
+/// Emit a `stake_refunded` event and return `amount` to the token contract.
+pub(crate) fn refund_stake(
+    sender_id: AccountId,
+    pool_id: Option<PoolId>,
+    amount: u128,
+    reason: RefundReason,
+) -> PromiseOrValue<U128> {
+    StakingEvent::StakeRefunded {
+        sender_id,
+        pool_id,
+        amount: U128(amount),
+        reason,
+    }
+    .emit();
+    PromiseOrValue::Value(U128(amount))
+}
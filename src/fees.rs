@@ -0,0 +1,173 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, Gas, NearToken, Promise};
+
+use crate::{StakingContract, StakingContractExt, AAR_BASE, U256};
+
+/// Maximum protocol fee (20%)
+pub const MAX_PROTOCOL_FEE_BPS: u128 = 2_000;
+
+/// Protocol fee settings
+#[near(serializers = [json])]
+pub struct ProtocolFeeConfig {
+    fee_bps: u128,                  // Share of each reward payout, in basis points
+    treasury_id: Option<AccountId>, // Receives fees right away when set
+}
+
+/// Protocol fees of a token
+#[near(serializers = [borsh])]
+#[derive(Default)]
+pub struct FeeBalance {
+    uncollected: u128, // Accumulated for `collect_fees`
+    total: u128,       // Taken so far
+}
+
+/// Protocol fees of a token
+#[near(serializers = [json])]
+pub struct FeeView {
+    token_id: AccountId,
+    uncollected: U128,
+    total: U128,
+}
+
+#[near]
+impl StakingContract {
+    /// Set the protocol fee (only callable by the owner).
+    /// - `fee_bps`: Share of each reward payout, 0 disables the fee.
+    /// - `treasury_id`: Account receiving each fee right away, `None` accumulates fees
+    ///   for `collect_fees`.
+    #[payable]
+    pub fn set_protocol_fee(&mut self, fee_bps: u128, treasury_id: Option<AccountId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set protocol fee."
+        );
+        require!(
+            fee_bps <= MAX_PROTOCOL_FEE_BPS,
+            "Cannot exceed MAX_PROTOCOL_FEE_BPS"
+        );
+        self.protocol_fee_bps = fee_bps;
+        self.treasury_id = treasury_id;
+        env::log_str(&format!(
+            "Protocol fee updated to {} for {:?}",
+            self.protocol_fee_bps, self.treasury_id
+        ));
+    }
+
+    /// Send the accumulated fees of a token to the treasury, or to the owner when no
+    /// treasury is set (only callable by the owner).
+    #[payable]
+    pub fn collect_fees(&mut self, token_id: AccountId) -> Promise {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can collect fees."
+        );
+        let mut balance = self.fees.get(&token_id).unwrap_or_default();
+        let amount = balance.uncollected;
+        require!(amount > 0, "No fees to collect");
+        balance.uncollected = 0;
+        self.fees.insert(&token_id, &balance);
+        let receiver_id = self.treasury_id.clone().unwrap_or(self.owner_id.clone());
+        env::log_str(&format!(
+            "Collect {} of {} fees to {}",
+            amount, token_id, receiver_id
+        ));
+        self.transfer_fee(token_id, receiver_id, amount)
+    }
+
+    /// Callback: After ft_transfer, keep the fees uncollected if the transfer failed.
+    #[private]
+    pub fn on_fee_transferred(
+        &mut self,
+        token_id: AccountId,
+        amount: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                let mut balance = self.fees.get(&token_id).unwrap_or_default();
+                balance.uncollected += amount;
+                self.fees.insert(&token_id, &balance);
+                false
+            }
+        }
+    }
+
+    /// Query protocol fee settings
+    pub fn get_protocol_fee(&self) -> ProtocolFeeConfig {
+        ProtocolFeeConfig {
+            fee_bps: self.protocol_fee_bps,
+            treasury_id: self.treasury_id.clone(),
+        }
+    }
+
+    /// Query the protocol fees taken so far, per token
+    pub fn get_fees(&self) -> Vec<FeeView> {
+        self.fees
+            .iter()
+            .map(|(token_id, balance)| FeeView {
+                token_id,
+                uncollected: U128(balance.uncollected),
+                total: U128(balance.total),
+            })
+            .collect()
+    }
+}
+
+impl StakingContract {
+    /// Protocol fee on a reward payout of `amount`.
+    pub(crate) fn protocol_fee(&self, amount: u128) -> u128 {
+        (U256::from(amount) * U256::from(self.protocol_fee_bps) / U256::from(AAR_BASE)).as_u128()
+    }
+
+    /// Record a fee taken from a payout, sent to the treasury when one is set.
+    pub(crate) fn take_fee(&mut self, token_id: &AccountId, fee: u128) {
+        if fee == 0 {
+            return;
+        }
+        let mut balance = self.fees.get(token_id).unwrap_or_default();
+        balance.total += fee;
+        match self.treasury_id.clone() {
+            Some(treasury_id) => {
+                self.fees.insert(token_id, &balance);
+                self.transfer_fee(token_id.clone(), treasury_id, fee);
+            }
+            None => {
+                balance.uncollected += fee;
+                self.fees.insert(token_id, &balance);
+            }
+        }
+        env::log_str(&format!("Protocol fee of {} {}", fee, token_id));
+    }
+
+    /// Accumulated fees of a token, kept out of `withdraw_token`.
+    pub(crate) fn uncollected_fees(&self, token_id: &AccountId) -> u128 {
+        self.fees
+            .get(token_id)
+            .map_or(0, |balance| balance.uncollected)
+    }
+
+    fn transfer_fee(&self, token_id: AccountId, receiver_id: AccountId, amount: u128) -> Promise {
+        Promise::new(token_id.clone())
+            .function_call(
+                "ft_transfer".to_string(),
+                serde_json::json!({
+                    "receiver_id": receiver_id,
+                    "amount": amount.to_string(),
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_gas(20_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                    .on_fee_transferred(token_id, amount),
+            )
+    }
+}
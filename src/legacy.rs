@@ -0,0 +1,20 @@
+use near_sdk::collections::UnorderedMap;
+use near_sdk::{near, AccountId};
+
+use crate::{StakeInfo, UserOperationState};
+
+/// State layout of version 1, kept to migrate deployed contracts.
+#[near(serializers = [borsh])]
+pub struct StakingContractV1 {
+    pub owner_id: AccountId,
+    pub token_contract: AccountId,
+    pub staked_balances: UnorderedMap<AccountId, StakeInfo>,
+    pub user_states: UnorderedMap<AccountId, UserOperationState>,
+    pub stake_start_time: u64,
+    pub lock_duration: u64,
+    pub stake_paused: bool,
+    pub stake_end_time: u64,
+    pub total_staked: u128,
+    pub total_claimed_reward: u128,
+    pub total_reward: u128,
+}
@@ -1,12 +1,50 @@
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
-use near_sdk::collections::UnorderedMap;
+use near_contract_standards::non_fungible_token::TokenId;
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet};
 use near_sdk::json_types::U128;
 use near_sdk::{
     assert_one_yocto, env, log, near, require, AccountId, Gas, NearToken, PanicOnDefault, Promise,
     PromiseOrValue,
 };
 use serde_json::json;
-const CURRENT_STATE_VERSION: u32 = 1;
+
+mod access;
+mod airdrop;
+mod close_out;
+mod emergency;
+mod events;
+mod fees;
+mod legacy;
+mod loyalty;
+mod nft_boost;
+mod payout;
+mod pool;
+mod referral;
+mod reward_tokens;
+mod season;
+mod seeding;
+mod settlement;
+mod solvency;
+mod storage;
+mod user_state;
+mod vesting;
+
+use access::AccessMode;
+use airdrop::Airdrop;
+use events::{refund_stake, RefundReason};
+use fees::FeeBalance;
+use pool::{parse_transfer_message, Pool, PoolId, DEFAULT_POOL_ID};
+use referral::ReferralFunding;
+use reward_tokens::AccountReward;
+use solvency::LiabilityRecompute;
+use storage::{StorageRegistrationMode, UnstakePayout, DEFAULT_STORAGE_DEPOSIT};
+
+use legacy::StakingContractV1;
+use loyalty::LoyaltyRecord;
+use nft_boost::NftBoost;
+use vesting::VestingSchedule;
+
+const CURRENT_STATE_VERSION: u32 = 2;
 const NO_DEPOSIT: NearToken = NearToken::from_near(0);
 const OUTER_UPGRADE_GAS: Gas = Gas::from_tgas(20);
 // Constants
@@ -15,9 +53,19 @@ const SECONDS_IN_A_YEAR: u128 = 365 * 24 * 60 * 60; // Number of seconds in a ye
 const WEEK: u64 = 7 * 24 * 60 * 60; // Number of seconds in a week
 const NANOSECONDS: u64 = 1_000_000_000; // Nanoseconds to seconds
 const AAR_BASE: u128 = 10000;
-const MAX_TOTAL_REWARD: u128 = 100000000_000_000_000_000_000_000;
+const MAX_TOTAL_REWARD: u128 = 100_000_000_000_000_000_000_000_000;
 const MAX_LOCK_DURATION: u64 = 4 * WEEK;
 const AAR_EARLY: [u128; 5] = [50000, 50000, 10000, 5000, 5000]; // Week 1,2,3,4,5 AAR
+
+#[allow(clippy::all)]
+mod u256 {
+    uint::construct_uint! {
+        /// 256-bit integer for intermediate products that can overflow u128
+        pub struct U256(4);
+    }
+}
+use u256::U256;
+
 /// Struct for storing staking information
 #[near(serializers = [json, borsh])]
 pub struct StakeInfo {
@@ -27,6 +75,16 @@ pub struct StakeInfo {
     start_time: u64,          // Timestamp when staking began
 }
 
+/// Independent pause switches
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct PauseStatus {
+    stake: bool,    // Pause ft_on_transfer staking
+    unstake: bool,  // Pause unstake
+    claim: bool,    // Pause reward payouts
+    withdraw: bool, // Pause owner token withdrawal
+}
+
 #[near(serializers = [json, borsh])]
 pub enum UserOperationState {
     Idle,
@@ -38,60 +96,198 @@ pub enum UserOperationState {
 #[near(contract_state)]
 pub struct StakingContract {
     owner_id: AccountId,                                      // Contract owner
-    token_contract: AccountId,                                // NEP-141 token contract address
-    staked_balances: UnorderedMap<AccountId, StakeInfo>,      // User staking information
+    pools: UnorderedMap<PoolId, Pool>,                        // Staking pools
     user_states: UnorderedMap<AccountId, UserOperationState>, // User operation state
-    stake_start_time: u64,                                    // Start time of stake
-    lock_duration: u64,                                       // Lock duration
-    stake_paused: bool,                                       // Pause stake
-    stake_end_time: u64, // Stake end time,after this time, there will be no rewards for stake,0 means no end time.
-    total_staked: u128,  // Total amount staked
-    total_claimed_reward: u128, // Total amount of claimed reward
-    total_reward: u128,  // Total amount of reward
+    user_state_times: LookupMap<AccountId, u64>,              // Time the user state was set
+    reward_accounts: LookupMap<(PoolId, AccountId, AccountId), AccountReward>, // (pool, token, account) reward accrual
+    pending_payouts: UnorderedMap<(PoolId, AccountId), u128>, // Payouts whose ft_transfer failed
+    earned_rewards: LookupMap<(PoolId, AccountId), u128>, // Rewards paid out or rolled over per pool
+    storage_registration_mode: StorageRegistrationMode,   // Handling of unregistered receivers
+    storage_budget: u128, // NEAR available to sponsor storage registrations
+    storage_deposit_amount: u128, // NEAR attached to a sponsored storage_deposit
+    guardian_id: Option<AccountId>, // Account allowed to enable emergency mode
+    emergency_mode: bool, // Emergency mode, only principal withdrawals are allowed
+    access_mode: AccessMode, // Who can stake
+    allowlist: UnorderedSet<AccountId>, // Accounts allowed to stake in allowlist mode
+    denylist: UnorderedSet<AccountId>, // Accounts blocked from staking and claiming rewards
+    frozen_rewards: LookupMap<(PoolId, AccountId), u128>, // Rewards owed to denylisted accounts
+    referrers: LookupMap<AccountId, AccountId>, // Referrer of each referred account
+    referral_counts: LookupMap<AccountId, u64>, // Accounts referred by each referrer
+    referral_earnings: LookupMap<(PoolId, AccountId), u128>, // Referral rewards per pool and referrer
+    loyalty_records: LookupMap<(PoolId, AccountId), LoyaltyRecord>, // Loyalty history per pool
+    boost_collections: UnorderedMap<AccountId, u128>, // NFT collections and their multipliers
+    nft_boosts: LookupMap<AccountId, NftBoost>,       // NFT backing the boost of each account
+    nft_holders: LookupMap<(AccountId, TokenId), AccountId>, // Account boosted by each NFT
+    nft_storage_deposits: LookupMap<AccountId, u128>, // Storage deposit paid by verify_nft
+    account_pools: LookupMap<AccountId, Vec<PoolId>>, // Pools where each account has a position
+    protocol_fee_bps: u128, // Protocol fee on reward payouts, in basis points
+    treasury_id: Option<AccountId>, // Receives protocol fees right away when set
+    fees: UnorderedMap<AccountId, FeeBalance>, // Protocol fees per token
+    vesting_schedules: LookupMap<(PoolId, AccountId), VestingSchedule>, // Vesting rewards per pool and account
+    airdrops: UnorderedMap<u64, Airdrop>, // Merkle airdrops of bonus rewards
+    airdrop_claims: LookupMap<(u64, u64), u128>, // Claimed bitmap of each airdrop, 128 leaves per word
+    liability_recompute: Option<LiabilityRecompute>, // Default pool liability left to count after migrate
+    liability_counted: LookupSet<AccountId>, // Default pool positions counted by recompute_liability
 }
 
 #[near]
 impl StakingContract {
-    /// Initialize the contract
+    /// Initialize the contract with the default pool
+    /// - `start_time`: Time the rewards start, defaults to now. Stakes made before it
+    ///   accrue nothing until then.
     #[init]
-    pub fn new(owner_id: AccountId, token_contract: AccountId, total_reward: U128) -> Self {
+    pub fn new(
+        owner_id: AccountId,
+        token_contract: AccountId,
+        total_reward: U128,
+        start_time: Option<u64>,
+    ) -> Self {
         assert!(!env::state_exists(), "Already initialized");
         let reward = total_reward.0;
         assert!(reward > 0, "Total reward should gt 0");
         let current_time = env::block_timestamp() / NANOSECONDS;
+        let start_time = start_time.unwrap_or(current_time);
+        require!(
+            start_time >= current_time,
+            "Start time cannot be in the past."
+        );
+        let mut pools = UnorderedMap::new(b"pools".to_vec());
+        let mut pool = Pool::new(
+            DEFAULT_POOL_ID,
+            token_contract,
+            reward,
+            2 * WEEK, // Lock 2 week on default
+            AAR_EARLY.to_vec(),
+            AAR,
+        );
+        pool.stake_start_time = start_time;
+        pools.insert(&DEFAULT_POOL_ID, &pool);
         Self {
             owner_id,
-            token_contract,
-            staked_balances: UnorderedMap::new(b"s".to_vec()),
+            pools,
             user_states: UnorderedMap::new(b"user_states".to_vec()),
-            stake_paused: false,
-            stake_start_time: current_time,
-            lock_duration: 2 * WEEK, // Lock 2 week on default
-            stake_end_time: 0,
-            total_staked: 0,
-            total_claimed_reward: 0,
-            total_reward: reward,
+            user_state_times: LookupMap::new(b"t".to_vec()),
+            reward_accounts: LookupMap::new(b"r".to_vec()),
+            pending_payouts: UnorderedMap::new(b"p".to_vec()),
+            earned_rewards: LookupMap::new(b"e".to_vec()),
+            storage_registration_mode: StorageRegistrationMode::Reject,
+            storage_budget: 0,
+            storage_deposit_amount: DEFAULT_STORAGE_DEPOSIT,
+            guardian_id: None,
+            emergency_mode: false,
+            access_mode: AccessMode::Open,
+            allowlist: UnorderedSet::new(b"al".to_vec()),
+            denylist: UnorderedSet::new(b"dl".to_vec()),
+            frozen_rewards: LookupMap::new(b"fz".to_vec()),
+            referrers: LookupMap::new(b"fr".to_vec()),
+            referral_counts: LookupMap::new(b"fc".to_vec()),
+            referral_earnings: LookupMap::new(b"fe".to_vec()),
+            loyalty_records: LookupMap::new(b"l".to_vec()),
+            boost_collections: UnorderedMap::new(b"nc".to_vec()),
+            nft_boosts: LookupMap::new(b"nb".to_vec()),
+            nft_holders: LookupMap::new(b"nh".to_vec()),
+            nft_storage_deposits: LookupMap::new(b"nd".to_vec()),
+            account_pools: LookupMap::new(b"ap".to_vec()),
+            protocol_fee_bps: 0,
+            treasury_id: None,
+            fees: UnorderedMap::new(b"g".to_vec()),
+            vesting_schedules: LookupMap::new(b"v".to_vec()),
+            airdrops: UnorderedMap::new(b"ad".to_vec()),
+            airdrop_claims: LookupMap::new(b"ac".to_vec()),
+            liability_recompute: None,
+            liability_counted: LookupSet::new(b"lc".to_vec()),
         }
     }
 
     /// Pause or start stake (only callable by the owner).
     /// - `pause`: If true, staking is paused, if false, staking is started.
+    /// - `pool_id`: Pool, defaults to the default pool.
     #[payable]
-    pub fn pause_stake(&mut self, pause: bool) {
+    pub fn pause_stake(&mut self, pause: bool, pool_id: Option<PoolId>) {
         assert_one_yocto();
         assert_eq!(
             self.owner_id,
             env::predecessor_account_id(),
             "Only the owner can pause or start stake."
         );
-        self.stake_paused = pause;
-        env::log_str(&format!("Stake paused updated to {}", self.stake_paused));
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.pause_status.stake = pause;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Stake paused of pool {} updated to {}",
+            pool_id, pause
+        ));
+    }
+
+    /// Pause or start unstake (only callable by the owner).
+    /// - `pause`: If true, unstaking is paused, if false, unstaking is started.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn pause_unstake(&mut self, pause: bool, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can pause or start unstake."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.pause_status.unstake = pause;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Unstake paused of pool {} updated to {}",
+            pool_id, pause
+        ));
+    }
+
+    /// Pause or start reward claims (only callable by the owner).
+    /// - `pause`: If true, no reward can be paid out, if false, claims are started.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn pause_claim(&mut self, pause: bool, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can pause or start claim."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.pause_status.claim = pause;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Claim paused of pool {} updated to {}",
+            pool_id, pause
+        ));
+    }
+
+    /// Pause or start owner token withdrawal (only callable by the owner).
+    /// - `pause`: If true, `withdraw_token` is paused, if false, it is started.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn pause_withdraw(&mut self, pause: bool, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can pause or start withdraw."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.pause_status.withdraw = pause;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Withdraw paused of pool {} updated to {}",
+            pool_id, pause
+        ));
     }
 
     /// Set lock duration (only callable by the owner).
     /// - `lock_duration`: Lock duration.
+    /// - `pool_id`: Pool, defaults to the default pool.
     #[payable]
-    pub fn set_lock_duration(&mut self, lock_duration: u64) {
+    pub fn set_lock_duration(&mut self, lock_duration: u64, pool_id: Option<PoolId>) {
         assert_one_yocto();
         assert_eq!(
             self.owner_id,
@@ -102,8 +298,14 @@ impl StakingContract {
             lock_duration <= MAX_LOCK_DURATION,
             "Cannot exceed MAX_LOCK_DURATION"
         );
-        self.lock_duration = lock_duration;
-        env::log_str(&format!("Lock duration updated to {}", self.lock_duration));
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.lock_duration = lock_duration;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Lock duration of pool {} updated to {}",
+            pool_id, lock_duration
+        ));
     }
 
     #[payable]
@@ -119,32 +321,92 @@ impl StakingContract {
         true
     }
     /// Set stake end time (only callable by the owner).
-    /// - `end_time`: End time timestamp.
+    /// - `end_time`: End time timestamp, a season cannot end after the next season starts.
+    /// - `pool_id`: Pool, defaults to the default pool.
     #[payable]
-    pub fn set_stake_end_time(&mut self, end_time: u64) {
+    pub fn set_stake_end_time(&mut self, end_time: u64, pool_id: Option<PoolId>) {
         assert_one_yocto();
         assert_eq!(
             self.owner_id,
             env::predecessor_account_id(),
             "Only the owner can set end time."
         );
-        if end_time == 0 {
-            // No end time
-            assert_eq!(self.stake_paused, false, "Need to start stake first.");
-        } else {
-            assert_eq!(self.stake_paused, true, "Need to pause stake first.");
+        // 0 means no end time, otherwise the end time cannot be in the past.
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        require!(
+            end_time == 0 || end_time >= current_time,
+            "End time cannot be in the past."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(!pool.finalized, "Pool is finalized");
+        require!(
+            end_time == 0 || end_time > pool.stake_start_time,
+            "End time should gt start time."
+        );
+        if let Some(next_id) = pool.next_season {
+            require!(
+                end_time != 0 && end_time <= self.load_pool(next_id).stake_start_time,
+                "Season cannot end after the next one starts"
+            );
+        }
+        pool.update_liability();
+        pool.stake_end_time = end_time;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Stake end time of pool {} updated to {}",
+            pool_id, end_time
+        ));
+    }
+
+    /// Schedule the start of the rewards (only callable by the owner), before it begins or
+    /// while nobody has staked yet. Stakes made before it accrue nothing until then.
+    /// - `start_time`: Start time timestamp, cannot be in the past.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn set_stake_start_time(&mut self, start_time: u64, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set start time."
+        );
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(
+            pool.stake_start_time > current_time || pool.stakes.is_empty(),
+            "Stake already started."
+        );
+        require!(
+            start_time >= current_time,
+            "Start time cannot be in the past."
+        );
+        require!(
+            pool.stake_end_time == 0 || start_time < pool.stake_end_time,
+            "Start time should lt end time."
+        );
+        if let Some(previous_id) = pool.previous_season {
+            require!(
+                start_time >= self.load_pool(previous_id).stake_end_time,
+                "Season cannot start before the previous one ends"
+            );
         }
-        self.stake_end_time = end_time;
+        // Nothing has accrued yet, the liability only needs to move past the old start
+        pool.update_liability();
+        pool.stake_start_time = start_time;
+        self.save_pool(pool_id, &pool);
         env::log_str(&format!(
-            "Stake end time updated to {}",
-            self.stake_end_time
+            "Stake start time of pool {} updated to {}",
+            pool_id, start_time
         ));
     }
 
     /// Set total reward (only callable by the owner).
     /// - `total_reward`: Total reward.
+    /// - `pool_id`: Pool, defaults to the default pool.
     #[payable]
-    pub fn set_total_reward(&mut self, total_reward: U128) {
+    pub fn set_total_reward(&mut self, total_reward: U128, pool_id: Option<PoolId>) {
         assert_one_yocto();
         assert_eq!(
             self.owner_id,
@@ -157,133 +419,90 @@ impl StakingContract {
             reward <= MAX_TOTAL_REWARD,
             "Total reward should le MAX_TOTAL_REWARD"
         );
-        self.total_reward = reward;
-        env::log_str(&format!("Total reward updated to {}", self.total_reward));
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(!pool.finalized, "Pool is finalized");
+        pool.total_reward = reward;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Total reward of pool {} updated to {}",
+            pool_id, reward
+        ));
     }
 
-    /// Unstake all principal and rewards
+    /// Unstake all principal and rewards of the default pool
     #[payable]
     pub fn unstake(&mut self) -> Promise {
+        self.unstake_in_pool(DEFAULT_POOL_ID, None)
+    }
+
+    /// Unstake principal and rewards of a pool
+    /// - `pool_id`: Pool.
+    /// - `amount`: Principal to unstake, defaults to the whole position. A partial unstake
+    ///   cannot leave less than the pool's minimum stake.
+    #[payable]
+    pub fn unstake_in_pool(&mut self, pool_id: PoolId, amount: Option<U128>) -> Promise {
         assert_one_yocto();
         let account_id = env::predecessor_account_id();
-        let mut stake_info = self
-            .staked_balances
+        let mut pool = self.load_pool(pool_id);
+        let stake_info = pool
+            .stakes
             .get(&account_id)
             .expect("No stake found for this account");
+        require!(
+            !self.emergency_mode,
+            "Emergency mode enabled, use emergency_withdraw"
+        );
+        require!(!pool.pause_status.unstake, "Unstake paused");
+        let amount = amount.map_or(stake_info.amount, |amount| amount.0);
+        require!(
+            amount > 0 && amount <= stake_info.amount,
+            "Invalid unstake amount"
+        );
+        let remaining = stake_info.amount - amount;
+        require!(
+            remaining == 0 || remaining >= pool.min_stake,
+            "Remaining stake below min stake"
+        );
 
+        // A state older than STUCK_STATE_TIMEOUT is left by a failed callback and is recovered.
+        let stale = self.is_user_state_stale(&account_id);
         match self.user_states.get(&account_id) {
-            Some(UserOperationState::Idle) | None => {
-                // pass
-                self.user_states
-                    .insert(&account_id, &UserOperationState::Unstaking);
-                env::log_str("Unstake operation started.");
-            }
-            Some(UserOperationState::Staking) => {
+            Some(UserOperationState::Staking) if !stale => {
                 env::panic_str("Cannot unstake while staking is in progress.");
             }
-            Some(UserOperationState::Unstaking) => {
+            Some(UserOperationState::Unstaking) if !stale => {
                 env::panic_str("Unstake operation already in progress.");
             }
-        }
-        // Calculate the time difference and accumulated rewards
-        let current_time = env::block_timestamp() / NANOSECONDS; // Convert nanoseconds to seconds
-        let reward_end_time = if self.stake_end_time == 0 {
-            current_time
-        } else {
-            std::cmp::min(current_time, self.stake_end_time)
-        };
-
-        let start_time = if reward_end_time >= stake_info.start_time {
-            stake_info.start_time
-        } else {
-            reward_end_time
-        };
-
-        // Update accumulated rewards
-        let reward = self.calculate_reward(stake_info.amount, reward_end_time, start_time);
-        let after_total_claimed_reward = self.total_claimed_reward + reward;
-        let mut claim_reward = 0;
-        // The user can only claim the portion that does not exceed the total reward.
-        if after_total_claimed_reward >= self.total_reward {
-            if self.total_reward >= self.total_claimed_reward {
-                claim_reward = self.total_reward - self.total_claimed_reward;
+            _ => {
+                self.set_user_state(&account_id, UserOperationState::Unstaking);
+                env::log_str("Unstake operation started.");
             }
-        } else {
-            claim_reward = reward;
         }
-        let before_accumulated_reward = stake_info.accumulated_reward;
-        stake_info.accumulated_reward += claim_reward;
-
-        let mut reward_amount = stake_info.accumulated_reward;
-        // Total payout = principal + accumulated rewards
-        // If the lock-up period is not exceeded, only the principal will be returned.
-        let total_payout = if current_time > stake_info.first_stake_time + self.lock_duration {
-            stake_info.amount + stake_info.accumulated_reward
-        } else {
-            reward_amount = 0;
-            stake_info.amount
-        };
-
-        // Remove staking record
-        self.staked_balances.remove(&account_id);
-
-        // Transfer principal and rewards to the user
-        Promise::new(self.token_contract.clone())
-            .function_call(
-                "ft_transfer".to_string(),
-                serde_json::json!({
-                    "receiver_id": account_id,
-                    "amount": total_payout.to_string(),
-                })
-                .to_string()
-                .into_bytes(),
-                NearToken::from_yoctonear(1), // Attach 1 yoctoNEAR
-                Gas::from_gas(20_000_000_000_000),
-            )
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
-                    .on_ft_transfer_then_remove(
-                        account_id,
-                        stake_info.amount,
-                        reward_amount,
-                        stake_info.first_stake_time,
-                        stake_info.start_time,
-                        before_accumulated_reward,
-                    ),
-            )
+        let (settled_info, payout) =
+            self.settle_unstake(pool_id, &mut pool, &account_id, stake_info, amount);
+        let staked_token = pool.staked_token.clone();
+        self.save_pool(pool_id, &pool);
+
+        // Transfer principal and rewards to the user once the receiver is registered,
+        // the position is restored with its settled reward if the unstake is rejected
+        self.check_storage_then_payout(pool_id, staked_token, account_id, settled_info, payout)
     }
 
-    /// Callback: After ft_transfer, only then remove staking record.
+    /// Callback: After ft_transfer, record the payout as pending if the transfer failed.
     #[private]
     pub fn on_ft_transfer_then_remove(
         &mut self,
+        pool_id: PoolId,
         account_id: AccountId,
-        stake_amount: u128,
-        reward_amount: u128,
-        first_stake_time: u64,
-        start_time: u64,
-        before_reward_amount: u128,
+        payout: u128,
         #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
     ) -> bool {
+        self.set_user_state(&account_id, UserOperationState::Idle);
         match call_result {
-            Ok(()) => {
-                self.total_staked -= stake_amount;
-                self.total_claimed_reward += reward_amount;
-                self.user_states
-                    .insert(&account_id, &UserOperationState::Idle);
-                true
-            }
+            Ok(()) => true,
             Err(_) => {
-                let stake_info = StakeInfo {
-                    amount: stake_amount,
-                    accumulated_reward: before_reward_amount,
-                    first_stake_time,
-                    start_time,
-                };
-                self.staked_balances.insert(&account_id, &stake_info);
-                self.user_states
-                    .insert(&account_id, &UserOperationState::Idle);
+                self.credit_pending_payout(pool_id, &account_id, payout);
                 false
             }
         }
@@ -291,23 +510,21 @@ impl StakingContract {
 
     /// Query staking information for a specific user
     pub fn get_stake_info(&self, account_id: AccountId) -> Option<StakeInfo> {
-        if let Some(mut stake_info) = self.staked_balances.get(&account_id) {
-            // Calculate the time difference
-            let current_time = env::block_timestamp() / NANOSECONDS; // Convert nanoseconds to seconds
-            let reward_end_time = if self.stake_end_time == 0 {
-                current_time
-            } else {
-                std::cmp::min(current_time, self.stake_end_time)
-            };
-
-            let start_time = if reward_end_time >= stake_info.start_time {
-                stake_info.start_time
-            } else {
-                reward_end_time
-            };
+        self.get_stake_info_in_pool(account_id, DEFAULT_POOL_ID)
+    }
 
-            // Calculate real-time rewards
-            let reward = self.calculate_reward(stake_info.amount, reward_end_time, start_time);
+    /// Query staking information for a specific user in a pool
+    pub fn get_stake_info_in_pool(
+        &self,
+        account_id: AccountId,
+        pool_id: PoolId,
+    ) -> Option<StakeInfo> {
+        let pool = self.load_pool(pool_id);
+        if let Some(mut stake_info) = pool.stakes.get(&account_id) {
+            // Calculate real-time rewards, including the loyalty and NFT bonuses
+            let reward = pool.pending_reward(&stake_info);
+            let reward =
+                reward + self.reward_bonus(pool_id, &pool, &account_id, &stake_info, reward);
 
             // Update the accumulated reward (real-time)
             stake_info.accumulated_reward += reward;
@@ -319,57 +536,29 @@ impl StakingContract {
         }
     }
 
-    /// Calculate rewards based on staking amount and duration
-    fn calculate_reward(&self, amount: u128, current_time: u64, start_time: u64) -> u128 {
-        let mut reward = 0u128;
-        // Reward formula: Principal * AAR * duration / (SECONDS_IN_A_YEAR * 10000)
-        for (index, aar) in AAR_EARLY.iter().enumerate() {
-            let aar_start_at = self.stake_start_time + (index as u64 * WEEK);
-            let aar_end_at = self.stake_start_time + ((index + 1) as u64 * WEEK);
-            // Skip if the entire interval is outside the range
-            if current_time < aar_start_at || start_time >= aar_end_at {
-                continue;
-            }
-            let reward_duration = if start_time >= aar_start_at {
-                if current_time <= aar_end_at {
-                    current_time - start_time
-                } else {
-                    aar_end_at - start_time
-                }
-            } else {
-                if current_time <= aar_end_at {
-                    current_time - aar_start_at
-                } else {
-                    aar_end_at - aar_start_at
-                }
-            };
-            reward += amount * aar * (reward_duration as u128);
-        }
-        let last_interval_end = self.stake_start_time + (AAR_EARLY.len() as u64 * WEEK);
-        if current_time >= last_interval_end {
-            let reward_duration = if start_time >= last_interval_end {
-                current_time - start_time
-            } else {
-                current_time - last_interval_end
-            };
-            reward += amount * AAR * (reward_duration as u128);
-        }
-        reward / (SECONDS_IN_A_YEAR * AAR_BASE)
-    }
-
     /// Query total stake
     pub fn get_total_stake(&self) -> u128 {
-        self.total_staked
+        self.get_total_stake_in_pool(DEFAULT_POOL_ID)
+    }
+
+    /// Query total stake of a pool
+    pub fn get_total_stake_in_pool(&self, pool_id: PoolId) -> u128 {
+        self.load_pool(pool_id).total_staked
     }
 
     /// Query total claimed reward
     pub fn get_total_claimed_reward(&self) -> u128 {
-        self.total_claimed_reward
+        self.get_total_claimed_reward_in_pool(DEFAULT_POOL_ID)
+    }
+
+    /// Query total claimed reward of a pool
+    pub fn get_total_claimed_reward_in_pool(&self, pool_id: PoolId) -> u128 {
+        self.load_pool(pool_id).total_claimed_reward
     }
 
-    /// Only owner can call. Transfer `amount` of given token to `to`.
+    /// Only owner can call. Transfer `amount` of the staked token of a pool to the owner.
     #[payable]
-    pub fn withdraw_token(&mut self, amount: U128) -> Promise {
+    pub fn withdraw_token(&mut self, amount: U128, pool_id: Option<PoolId>) -> Promise {
         assert_one_yocto();
         // Ensure only owner can call
         assert_eq!(
@@ -378,9 +567,11 @@ impl StakingContract {
             "Only the owner can withdraw tokens"
         );
 
-        assert_eq!(self.stake_paused, true, "Stake should paused");
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let pool = self.load_pool(pool_id);
+        require!(!pool.pause_status.withdraw, "Withdraw paused");
 
-        Promise::new(self.token_contract.clone())
+        Promise::new(pool.staked_token.clone())
             .function_call(
                 "ft_balance_of".to_string(),
                 serde_json::json!({
@@ -394,18 +585,14 @@ impl StakingContract {
             .then(
                 Self::ext(env::current_account_id())
                     .with_static_gas(Gas::from_gas(30_000_000_000_000))
-                    .on_check_balance_then_withdraw(
-                        self.token_contract.clone(),
-                        self.owner_id.clone(),
-                        amount,
-                    ),
+                    .on_check_balance_then_withdraw(pool_id, self.owner_id.clone(), amount),
             )
     }
 
     #[private]
     pub fn on_check_balance_then_withdraw(
-        &self,
-        token_contract: AccountId,
+        &mut self,
+        pool_id: PoolId,
         to: AccountId,
         amount: U128,
         #[callback_result] call_result: Result<Option<U128>, near_sdk::PromiseError>,
@@ -414,21 +601,36 @@ impl StakingContract {
             Ok(Some(b)) => b.0,
             _ => env::panic_str("Failed to get token balance"),
         };
-        let mut available = 0;
-        let mut frozen = self.total_staked;
-        if self.total_reward >= self.total_claimed_reward {
-            frozen += self.total_reward - self.total_claimed_reward;
-        }
-
-        if balance > frozen {
-            available = balance - frozen;
-        }
+        // Only principal, pending payouts and rewards accrued so far are reserved.
+        // Other pools and reward budgets paid in the same token keep their full reservation.
+        let mut pool = self.load_pool(pool_id);
+        pool.update_liability();
+        let reserved_elsewhere = self.reserved_balance(&pool.staked_token, pool_id);
+        let available = balance.saturating_sub(reserved_elsewhere + pool.owed_balance());
         assert!(
             amount.0 <= available,
             "Not enough token balance to withdraw"
         );
 
-        Promise::new(token_contract).function_call(
+        // Withdrawing the part of the budget that has not accrued yet lowers total_reward
+        let unreserved = balance.saturating_sub(
+            reserved_elsewhere
+                + pool.total_staked
+                + pool.total_pending_payout
+                + pool.total_vesting
+                + pool.airdrop_reserve
+                + pool.remaining_reward_budget(),
+        );
+        if amount.0 > unreserved {
+            pool.total_reward -= amount.0 - unreserved;
+            env::log_str(&format!(
+                "Total reward of pool {} updated to {}",
+                pool_id, pool.total_reward
+            ));
+        }
+        self.save_pool(pool_id, &pool);
+
+        Promise::new(pool.staked_token).function_call(
             "ft_transfer".to_string(),
             serde_json::json!({
                 "receiver_id": to,
@@ -443,9 +645,100 @@ impl StakingContract {
 
     #[private]
     #[init(ignore_state)]
-    #[allow(unused_variables)]
     pub fn migrate(from_version: u32) -> Self {
-        env::state_read().unwrap_or_else(|| env::panic_str("ERR_FAILED_TO_READ_STATE"))
+        if from_version >= CURRENT_STATE_VERSION {
+            return env::state_read().unwrap_or_else(|| env::panic_str("ERR_FAILED_TO_READ_STATE"));
+        }
+        let old: StakingContractV1 =
+            env::state_read().unwrap_or_else(|| env::panic_str("ERR_FAILED_TO_READ_STATE"));
+        // The single pool becomes the default pool, its stakes keep their storage prefix
+        let mut pool = Pool {
+            staked_token: old.token_contract,
+            stakes: old.staked_balances,
+            stake_start_time: old.stake_start_time,
+            stake_end_time: old.stake_end_time,
+            lock_duration: old.lock_duration,
+            early_aar: AAR_EARLY.to_vec(),
+            aar: AAR,
+            pause_status: PauseStatus {
+                stake: old.stake_paused,
+                ..Default::default()
+            },
+            total_staked: old.total_staked,
+            total_claimed_reward: old.total_claimed_reward,
+            total_reward: old.total_reward,
+            accrued_reward_liability: 0,
+            liability_updated_at: 0,
+            reward_tokens: Vec::new(),
+            total_pending_payout: 0,
+            previous_season: None,
+            next_season: None,
+            max_stake_per_account: None,
+            max_total_staked: None,
+            min_stake: 0,
+            total_settled_reward: 0,
+            reward_remainder: 0,
+            referral_bps: 0,
+            referral_funding: ReferralFunding::TotalReward,
+            referral_budget: 0,
+            total_referral_reward: 0,
+            loyalty: None,
+            total_bonus_reward: 0,
+            vesting: None,
+            total_vesting: 0,
+            airdrop_reserve: 0,
+            finalized: false,
+            swept_reward: 0,
+        };
+        // Positions are counted into the liability in batches by `recompute_liability`,
+        // what they accrue from now on is added by `update_liability`
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        pool.liability_updated_at = pool.reward_end_time(current_time);
+        let liability_recompute = if pool.stakes.is_empty() {
+            None
+        } else {
+            Some(LiabilityRecompute::new(
+                pool.stakes.len(),
+                pool.liability_updated_at,
+            ))
+        };
+        let mut pools = UnorderedMap::new(b"pools".to_vec());
+        pools.insert(&DEFAULT_POOL_ID, &pool);
+        Self {
+            owner_id: old.owner_id,
+            pools,
+            user_states: old.user_states,
+            user_state_times: LookupMap::new(b"t".to_vec()),
+            reward_accounts: LookupMap::new(b"r".to_vec()),
+            pending_payouts: UnorderedMap::new(b"p".to_vec()),
+            earned_rewards: LookupMap::new(b"e".to_vec()),
+            storage_registration_mode: StorageRegistrationMode::Reject,
+            storage_budget: 0,
+            storage_deposit_amount: DEFAULT_STORAGE_DEPOSIT,
+            guardian_id: None,
+            emergency_mode: false,
+            access_mode: AccessMode::Open,
+            allowlist: UnorderedSet::new(b"al".to_vec()),
+            denylist: UnorderedSet::new(b"dl".to_vec()),
+            frozen_rewards: LookupMap::new(b"fz".to_vec()),
+            referrers: LookupMap::new(b"fr".to_vec()),
+            referral_counts: LookupMap::new(b"fc".to_vec()),
+            referral_earnings: LookupMap::new(b"fe".to_vec()),
+            loyalty_records: LookupMap::new(b"l".to_vec()),
+            boost_collections: UnorderedMap::new(b"nc".to_vec()),
+            nft_boosts: LookupMap::new(b"nb".to_vec()),
+            nft_holders: LookupMap::new(b"nh".to_vec()),
+            nft_storage_deposits: LookupMap::new(b"nd".to_vec()),
+            account_pools: LookupMap::new(b"ap".to_vec()),
+            protocol_fee_bps: 0,
+            treasury_id: None,
+            fees: UnorderedMap::new(b"g".to_vec()),
+            vesting_schedules: LookupMap::new(b"v".to_vec()),
+            airdrops: UnorderedMap::new(b"ad".to_vec()),
+            airdrop_claims: LookupMap::new(b"ac".to_vec()),
+            liability_recompute,
+            liability_counted: LookupSet::new(b"lc".to_vec()),
+        }
     }
 
     pub fn update_contract(&self) {
@@ -483,24 +776,50 @@ impl StakingContract {
         self.owner_id.clone()
     }
 
-    /// Query aar
-    pub fn get_aar(&self) -> [u128; 5] {
-        AAR_EARLY
+    /// Query the weekly early aar
+    pub fn get_aar(&self) -> Vec<u128> {
+        self.get_aar_in_pool(DEFAULT_POOL_ID)
+    }
+
+    /// Query the weekly early aar of a pool
+    pub fn get_aar_in_pool(&self, pool_id: PoolId) -> Vec<u128> {
+        self.load_pool(pool_id).early_aar
+    }
+
+    /// Query pause switches
+    pub fn get_pause_status(&self, pool_id: Option<PoolId>) -> PauseStatus {
+        self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID))
+            .pause_status
     }
 
     /// Query lock duration
     pub fn get_lock_duration(&self) -> u64 {
-        self.lock_duration
+        self.get_lock_duration_in_pool(DEFAULT_POOL_ID)
+    }
+
+    /// Query lock duration of a pool
+    pub fn get_lock_duration_in_pool(&self, pool_id: PoolId) -> u64 {
+        self.load_pool(pool_id).lock_duration
     }
 
     pub fn search_stake_infos(
         &self,
         offset: Option<u64>,
         limit: Option<u64>,
+    ) -> Vec<(AccountId, StakeInfo)> {
+        self.search_stake_infos_in_pool(DEFAULT_POOL_ID, offset, limit)
+    }
+
+    pub fn search_stake_infos_in_pool(
+        &self,
+        pool_id: PoolId,
+        offset: Option<u64>,
+        limit: Option<u64>,
     ) -> Vec<(AccountId, StakeInfo)> {
         let start = offset.unwrap_or(0);
         let l = limit.unwrap_or(50);
-        self.staked_balances
+        self.load_pool(pool_id)
+            .stakes
             .iter()
             .skip(start as usize)
             .take(l as usize)
@@ -508,64 +827,316 @@ impl StakingContract {
     }
 }
 
-/// Implementation of NEP-141 `ft_on_transfer` method
-#[near]
-impl FungibleTokenReceiver for StakingContract {
-    /// Handle token transfers for staking
-    fn ft_on_transfer(
+impl StakingContract {
+    /// Bring the pool accumulators up to date and settle the partner rewards of an account.
+    /// Must run before the stake amount of the account or `total_staked` changes.
+    pub(crate) fn checkpoint_account(
         &mut self,
-        sender_id: AccountId,
-        amount: U128,
-        msg: String,
-    ) -> PromiseOrValue<U128> {
-        // Ensure that the token being transferred is the one specified in the contract
-        assert_eq!(
-            env::predecessor_account_id(),
-            self.token_contract,
-            "Only the specified token can be staked"
-        );
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        stake_amount: u128,
+    ) {
+        self.assert_liability_counted(pool_id);
+        pool.update_liability();
+        pool.update_reward_tokens();
+        self.settle_reward_tokens(pool_id, pool, account_id, stake_amount);
+    }
+
+    /// Settle the pending reward of a position and add the loyalty and NFT bonuses of the
+    /// account. The NFT multiplier applies to the whole period since the last settlement,
+    /// boost changes settle first. The loyalty multiplier follows the tier reached at each
+    /// point of the period.
+    pub(crate) fn settle_boosted_reward(
+        &self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        stake_info: &StakeInfo,
+    ) -> u128 {
+        let reward = pool.settle_reward(stake_info);
+        let bonus = self.reward_bonus(pool_id, pool, account_id, stake_info, reward);
+        // The liability only accrues the base rate, the bonus is owed from now on
+        pool.accrued_reward_liability += bonus;
+        pool.total_bonus_reward += bonus;
+        reward + bonus
+    }
 
-        assert_eq!(self.stake_paused, false, "Stake paused");
+    /// Extra reward on top of `reward`, the pending reward of a position, from the
+    /// multipliers of the account.
+    pub(crate) fn reward_bonus(
+        &self,
+        pool_id: PoolId,
+        pool: &Pool,
+        account_id: &AccountId,
+        stake_info: &StakeInfo,
+        reward: u128,
+    ) -> u128 {
+        self.loyalty_bonus(pool_id, pool, account_id, stake_info)
+            + self.nft_bonus(account_id, reward)
+    }
 
-        match self.user_states.get(&sender_id) {
-            Some(UserOperationState::Idle) | None => {
-                self.user_states
-                    .insert(&sender_id, &UserOperationState::Staking);
-                env::log_str("Stake operation started.");
-            }
-            Some(UserOperationState::Staking) => {
-                env::panic_str("Stake operation already in progress.");
-            }
-            Some(UserOperationState::Unstaking) => {
-                env::panic_str("Cannot stake while unstake is in progress.");
-            }
+    /// Settle the rewards of a position and take `amount` of principal out of it, with the
+    /// rules of `unstake`. Returns the position with its settled reward, restored if the
+    /// payout is rejected, and the payout. The caller saves the pool.
+    pub(crate) fn settle_unstake(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        stake_info: StakeInfo,
+        amount: u128,
+    ) -> (StakeInfo, UnstakePayout) {
+        let remaining = stake_info.amount - amount;
+        self.checkpoint_account(pool_id, pool, account_id, stake_info.amount);
+        let current_time = env::block_timestamp() / NANOSECONDS; // Convert nanoseconds to seconds
+
+        // Update accumulated rewards
+        let reward = self.settle_boosted_reward(pool_id, pool, account_id, &stake_info);
+        // Rewards are scaled down pro-rata once the liability exceeds the remaining budget.
+        let owed_reward = stake_info.accumulated_reward + reward;
+        let mut reward_amount = pool.apply_haircut(owed_reward);
+        // Total payout = principal + accumulated rewards
+        // If the lock-up period is not exceeded, only the principal will be returned and
+        // the rewards are forfeited, or kept in the position after a partial unstake.
+        // Once the pool has ended, positions exit without penalty.
+        // Denylisted accounts and unstakes while claims are paused get their principal back,
+        // the reward is kept for later.
+        let unlocked = current_time > stake_info.first_stake_time + pool.lock_duration
+            || pool.has_ended(current_time);
+        let pays_reward =
+            unlocked && !self.denylist.contains(account_id) && !pool.pause_status.claim;
+        if !pays_reward {
+            reward_amount = 0;
+        }
+        // The protocol fee is taken from the reward once the payout goes through,
+        // in a vesting pool the rest of the reward vests instead of being transferred
+        let fee = self.protocol_fee(reward_amount);
+        let vested = if pool.vesting.is_some() {
+            reward_amount - fee
+        } else {
+            0
+        };
+        let total_payout = amount + reward_amount - fee - vested;
+
+        // Whatever is not paid out is forfeited, either way it is no longer owed.
+        // A reward held back by the denylist or a claim pause is kept in the position, or
+        // frozen when the position is closed, and paid once it is lifted.
+        let frozen = if unlocked && !pays_reward && remaining == 0 {
+            self.freeze_reward(pool_id, account_id, owed_reward);
+            owed_reward
+        } else {
+            0
+        };
+        let kept_reward = if frozen > 0 {
+            0
+        } else if pays_reward || remaining == 0 {
+            pool.release_liability(owed_reward);
+            0
+        } else {
+            owed_reward
+        };
+
+        // Update staking record, a failed transfer is recorded as a pending payout
+        if remaining == 0 {
+            pool.stakes.remove(account_id);
+        } else {
+            pool.stakes.insert(
+                account_id,
+                &StakeInfo {
+                    amount: remaining,
+                    accumulated_reward: kept_reward,
+                    first_stake_time: stake_info.first_stake_time,
+                    start_time: current_time,
+                },
+            );
         }
+        self.update_loyalty(pool_id, pool, account_id, remaining);
+        self.track_position(pool_id, account_id, remaining);
+        pool.total_staked -= amount;
+        pool.total_claimed_reward += reward_amount;
+        self.record_earned_reward(pool_id, account_id, reward_amount);
+
+        let settled_info = StakeInfo {
+            accumulated_reward: owed_reward,
+            start_time: current_time,
+            ..stake_info
+        };
+        let payout = UnstakePayout {
+            principal: amount,
+            withdrawn: owed_reward - kept_reward,
+            reward: reward_amount,
+            fee,
+            vested,
+            frozen,
+            total: total_payout,
+        };
+        (settled_info, payout)
+    }
+
+    /// Add `amount` to the position of an account, creating it if needed.
+    pub(crate) fn add_stake(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
         // Get the current timestamp
         let current_time = env::block_timestamp() / NANOSECONDS; // Convert nanoseconds to seconds
 
-        // Update or create the user's staking record
-        let mut stake_info = self.staked_balances.get(&sender_id).unwrap_or(StakeInfo {
+        // Update or create the user's staking record, the lock counts from the pool start
+        let mut stake_info = pool.stakes.get(account_id).unwrap_or(StakeInfo {
             amount: 0,
             accumulated_reward: 0,
-            first_stake_time: current_time,
+            first_stake_time: std::cmp::max(current_time, pool.stake_start_time),
             start_time: current_time,
         });
 
         // Update accumulated rewards
-        let reward = self.calculate_reward(stake_info.amount, current_time, stake_info.start_time);
+        self.checkpoint_account(pool_id, pool, account_id, stake_info.amount);
+        let reward = self.settle_boosted_reward(pool_id, pool, account_id, &stake_info);
         stake_info.accumulated_reward += reward;
 
         // Update principal and timestamp
-        stake_info.amount += amount.0;
+        stake_info.amount += amount;
         stake_info.start_time = current_time;
 
-        self.staked_balances.insert(&sender_id, &stake_info);
+        pool.stakes.insert(account_id, &stake_info);
+        self.update_loyalty(pool_id, pool, account_id, stake_info.amount);
+        self.track_position(pool_id, account_id, stake_info.amount);
+
+        pool.total_staked += amount;
+    }
+
+    /// Add or remove a pool from the pools where an account has a position, after its
+    /// amount in the pool changed to `amount`.
+    pub(crate) fn track_position(&mut self, pool_id: PoolId, account_id: &AccountId, amount: u128) {
+        let mut pool_ids = self.account_pools.get(account_id).unwrap_or_default();
+        let tracked = pool_ids.contains(&pool_id);
+        if amount > 0 && !tracked {
+            pool_ids.push(pool_id);
+        } else if amount == 0 && tracked {
+            pool_ids.retain(|id| *id != pool_id);
+        } else {
+            return;
+        }
+        if pool_ids.is_empty() {
+            self.account_pools.remove(account_id);
+        } else {
+            self.account_pools.insert(account_id, &pool_ids);
+        }
+    }
+}
+
+/// Implementation of NEP-141 `ft_on_transfer` method
+#[near]
+impl FungibleTokenReceiver for StakingContract {
+    /// Handle token transfers for staking, `msg` selects the pool
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let Some(message) = parse_transfer_message(&msg) else {
+            return refund_stake(sender_id, None, amount.0, RefundReason::InvalidMsg);
+        };
+        let pool_id = message.pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let Some(mut pool) = self.pools.get(&pool_id) else {
+            return refund_stake(
+                sender_id,
+                Some(pool_id),
+                amount.0,
+                RefundReason::PoolNotFound,
+            );
+        };
+        if pool.finalized {
+            return refund_stake(
+                sender_id,
+                Some(pool_id),
+                amount.0,
+                RefundReason::PoolFinalized,
+            );
+        }
+        // Tokens from a reward token contract fund its budget
+        let token_id = env::predecessor_account_id();
+        if token_id != pool.staked_token {
+            let Some(index) = pool.reward_token_index(&token_id) else {
+                return refund_stake(sender_id, Some(pool_id), amount.0, RefundReason::WrongToken);
+            };
+            pool.fund_reward_token(index, amount.0);
+            self.save_pool(pool_id, &pool);
+            return PromiseOrValue::Value(U128(0));
+        }
+        if message.referral_budget {
+            pool.referral_budget += amount.0;
+            self.save_pool(pool_id, &pool);
+            env::log_str(&format!(
+                "Referral budget of pool {} updated to {}",
+                pool_id, pool.referral_budget
+            ));
+            return PromiseOrValue::Value(U128(0));
+        }
+        if let Some(records) = message.seed {
+            let result = if sender_id != self.owner_id {
+                Err(RefundReason::Unauthorized)
+            } else {
+                self.seed_stakes(pool_id, &mut pool, records, amount.0)
+            };
+            return match result {
+                Ok(()) => {
+                    self.save_pool(pool_id, &pool);
+                    PromiseOrValue::Value(U128(0))
+                }
+                Err(reason) => refund_stake(sender_id, Some(pool_id), amount.0, reason),
+            };
+        }
 
-        self.total_staked += amount.0;
+        // Rejected transfers are returned to the sender with the reason
+        let rejection = if !self.can_stake(&sender_id) {
+            Some(RefundReason::AccessDenied)
+        } else if pool.pause_status.stake {
+            Some(RefundReason::StakePaused)
+        } else if self.emergency_mode {
+            Some(RefundReason::EmergencyMode)
+        } else if matches!(
+            self.user_states.get(&sender_id),
+            Some(UserOperationState::Staking | UserOperationState::Unstaking)
+        ) && !self.is_user_state_stale(&sender_id)
+        {
+            // A state older than STUCK_STATE_TIMEOUT is left by a failed callback and is recovered.
+            Some(RefundReason::OperationInProgress)
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            return refund_stake(sender_id, Some(pool_id), amount.0, reason);
+        }
+        self.set_user_state(&sender_id, UserOperationState::Staking);
+        env::log_str("Stake operation started.");
+
+        // The portion above the stake caps is refunded
+        let staked = pool
+            .stakes
+            .get(&sender_id)
+            .map(|stake_info| stake_info.amount)
+            .unwrap_or(0);
+        let (accepted, reason) = pool.admitted_stake(staked, amount.0);
+        if accepted > 0 {
+            self.add_stake(pool_id, &mut pool, &sender_id, accepted);
+            self.save_pool(pool_id, &pool);
+            if let Some(referrer_id) = message.referrer {
+                self.set_referrer(&sender_id, referrer_id);
+            }
+        }
+        self.set_user_state(&sender_id, UserOperationState::Idle);
+        let refund = amount.0 - accepted;
+        if refund > 0 {
+            return refund_stake(sender_id, Some(pool_id), refund, reason);
+        }
 
-        self.user_states
-            .insert(&sender_id, &UserOperationState::Idle);
-        // Return 0 to indicate the transfer was successfully handled
+        // The transfer was fully staked
         PromiseOrValue::Value(U128(0))
     }
 }
@@ -573,12 +1144,36 @@ impl FungibleTokenReceiver for StakingContract {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use near_sdk::json_types::U128;
+    use close_out::CLOSE_OUT_GRACE_PERIOD;
+    use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageBalance;
+    use near_sdk::json_types::{Base58CryptoHash, U128};
     use near_sdk::test_utils::accounts;
-    use near_sdk::{test_utils::VMContextBuilder, testing_env, AccountId};
+    use near_sdk::{test_utils::VMContextBuilder, testing_env, AccountId, CryptoHash};
+    use nft_boost::NFT_BOOST_STORAGE_DEPOSIT;
 
     const TOKEN_CONTRACT: &str = "token.testnet";
 
+    /// Two-leaf airdrop tree of (index, account, amount), returns the root and the leaves.
+    fn airdrop_tree(allocations: [(AccountId, u128); 2]) -> (Base58CryptoHash, Vec<CryptoHash>) {
+        let leaves: Vec<CryptoHash> = allocations
+            .iter()
+            .enumerate()
+            .map(|(index, (account_id, amount))| {
+                env::sha256_array(
+                    &near_sdk::borsh::to_vec(&(index as u64, account_id, *amount)).unwrap(),
+                )
+            })
+            .collect();
+        let (left, right) = if leaves[0] <= leaves[1] {
+            (leaves[0], leaves[1])
+        } else {
+            (leaves[1], leaves[0])
+        };
+        let root = env::sha256_array(&[left, right].concat());
+        (root.into(), leaves)
+    }
+
     /// Helper function to create a mock context
     fn get_context(
         predecessor: AccountId,
@@ -601,12 +1196,19 @@ mod tests {
 
         // Initialize the contract
         let token_contract: AccountId = TOKEN_CONTRACT.parse().unwrap();
-        let contract =
-            StakingContract::new(accounts(0), token_contract.clone(), U128(1_000_000u128));
-
+        let contract = StakingContract::new(
+            accounts(0),
+            token_contract.clone(),
+            U128(1_000_000u128),
+            None,
+        );
+
         // Check initialization
         assert_eq!(contract.owner_id, accounts(0));
-        assert_eq!(contract.token_contract, token_contract);
+        assert_eq!(
+            contract.load_pool(DEFAULT_POOL_ID).staked_token,
+            token_contract
+        );
     }
 
     #[test]
@@ -620,6 +1222,7 @@ mod tests {
             accounts(0),
             TOKEN_CONTRACT.parse().unwrap(),
             U128(1_000_000u128),
+            None,
         );
 
         // Simulate a user staking tokens via ft_on_transfer
@@ -645,6 +1248,7 @@ mod tests {
             accounts(0),
             TOKEN_CONTRACT.parse().unwrap(),
             U128(1_000_000u128),
+            None,
         );
 
         // Simulate a user staking tokens multiple times
@@ -676,6 +1280,7 @@ mod tests {
             accounts(0),
             TOKEN_CONTRACT.parse().unwrap(),
             U128(1_000_000u128),
+            None,
         );
 
         // Simulate a user staking tokens
@@ -714,6 +1319,7 @@ mod tests {
             accounts(0),
             TOKEN_CONTRACT.parse().unwrap(),
             U128(1_000_000u128),
+            None,
         );
 
         // Simulate a user staking tokens
@@ -749,6 +1355,7 @@ mod tests {
             accounts(0),
             TOKEN_CONTRACT.parse().unwrap(),
             U128(1_000_000u128),
+            None,
         );
 
         // Simulate time passing (1 year)
@@ -786,6 +1393,7 @@ mod tests {
             accounts(0),
             TOKEN_CONTRACT.parse().unwrap(),
             U128(1_000_000u128),
+            None,
         );
 
         // Simulate a user staking tokens
@@ -803,12 +1411,9 @@ mod tests {
         contract.unstake();
         let stake = stake_info.unwrap();
         contract.on_ft_transfer_then_remove(
+            DEFAULT_POOL_ID,
             accounts(1),
-            stake.amount,
-            stake.accumulated_reward,
-            stake.first_stake_time,
-            stake.start_time,
-            stake.accumulated_reward,
+            stake.amount + stake.accumulated_reward,
             Ok(()),
         );
         // Check that the user's staking record is removed
@@ -820,4 +1425,2127 @@ mod tests {
             stake.accumulated_reward
         );
     }
+
+    #[test]
+    fn test_emergency_withdraw() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        // Owner enables emergency mode
+        let context = get_context(accounts(0), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.set_emergency_mode(true);
+
+        // Staker withdraws the principal without waiting for the lock
+        let context = get_context(sender_id.clone(), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let stake = contract.get_stake_info(sender_id.clone()).unwrap();
+        contract.emergency_withdraw(None);
+        contract.on_emergency_withdraw(DEFAULT_POOL_ID, sender_id.clone(), stake.amount, Ok(()));
+
+        assert!(contract.get_stake_info(sender_id).is_none());
+        assert_eq!(contract.get_total_stake(), 0);
+        assert_eq!(contract.get_total_claimed_reward(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can disable emergency mode.")]
+    fn test_guardian_cannot_disable_emergency_mode() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_guardian(Some(accounts(2)));
+
+        let context = get_context(accounts(2), 1, 0);
+        testing_env!(context.build());
+        contract.set_emergency_mode(true);
+        assert!(contract.is_emergency_mode());
+        contract.set_emergency_mode(false);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unstake paused")]
+    fn test_pause_unstake() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+        contract.pause_unstake(true, None);
+        let status = contract.get_pause_status(None);
+        assert!(status.unstake);
+        assert!(!status.stake && !status.claim && !status.withdraw);
+
+        // Staking stays open while unstaking is paused
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(1), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+    }
+
+    #[test]
+    fn test_recover_stuck_user_state() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        // Unstake without running the callback leaves the account in Unstaking
+        let unstake_time = 5 * WEEK;
+        let context = get_context(sender_id.clone(), 1, unstake_time * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+
+        let stuck = contract.get_stuck_accounts(None, None);
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].0, sender_id);
+        assert!(matches!(stuck[0].1, UserOperationState::Unstaking));
+        assert_eq!(stuck[0].2, unstake_time);
+
+        // After the timeout the account recovers by itself
+        let context = get_context(
+            TOKEN_CONTRACT.parse().unwrap(),
+            0,
+            (unstake_time + user_state::STUCK_STATE_TIMEOUT) * NANOSECONDS,
+        );
+        testing_env!(context.build());
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000), "".to_string());
+        assert!(contract.get_stuck_accounts(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_owner_reset_user_state() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        let context = get_context(sender_id.clone(), 1, 0);
+        testing_env!(context.build());
+        contract.unstake();
+
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+        contract.reset_user_state(sender_id.clone());
+        assert!(contract.get_stuck_accounts(None, None).is_empty());
+
+        // The account can stake again right away
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000), "".to_string());
+        assert_eq!(contract.get_stake_info(sender_id).unwrap().amount, 1_000);
+    }
+
+    #[test]
+    fn test_failed_payout_is_pending() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let stake = contract.get_stake_info(sender_id.clone()).unwrap();
+        let payout = stake.amount + stake.accumulated_reward;
+        contract.unstake();
+        contract.on_ft_transfer_then_remove(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            payout,
+            Err(near_sdk::PromiseError::Failed),
+        );
+
+        // The stake is settled and the payout is kept in the ledger
+        assert!(contract.get_stake_info(sender_id.clone()).is_none());
+        assert_eq!(contract.get_total_stake(), 0);
+        assert_eq!(contract.get_pending_payout(sender_id.clone(), None), payout);
+        assert_eq!(contract.get_total_pending_payout(None), payout);
+
+        // Redirect the payout to another account, a failed retry credits it back
+        contract.withdraw_pending(Some(accounts(2)), None);
+        assert_eq!(contract.get_pending_payout(sender_id.clone(), None), 0);
+        contract.on_withdraw_pending(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            payout,
+            Err(near_sdk::PromiseError::Failed),
+        );
+        assert_eq!(contract.get_pending_payout(sender_id, None), payout);
+    }
+
+    #[test]
+    fn test_unstake_rejected_for_unregistered_receiver() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let before = contract.get_stake_info(sender_id.clone()).unwrap();
+        let stake_info = contract
+            .load_pool(DEFAULT_POOL_ID)
+            .stakes
+            .get(&sender_id)
+            .unwrap();
+        contract.unstake();
+        let result = contract.on_storage_balance_checked(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            stake_info,
+            UnstakePayout {
+                principal: before.amount,
+                withdrawn: before.accumulated_reward,
+                reward: before.accumulated_reward,
+                fee: 0,
+                vested: 0,
+                frozen: 0,
+                total: before.amount + before.accumulated_reward,
+            },
+            Ok(None),
+        );
+
+        // The stake is restored and the account can retry once registered
+        assert!(matches!(result, PromiseOrValue::Value(false)));
+        let after = contract.get_stake_info(sender_id.clone()).unwrap();
+        assert_eq!(after.amount, before.amount);
+        assert_eq!(after.accumulated_reward, before.accumulated_reward);
+        assert!(before.accumulated_reward > 0);
+        assert_eq!(contract.get_total_stake(), before.amount);
+        assert_eq!(contract.get_total_claimed_reward(), 0);
+        assert!(contract.get_stuck_accounts(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_claim_pause_keeps_reward_and_pays_principal() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        contract.ft_on_transfer(accounts(2), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.pause_claim(true, None);
+
+        // A partial unstake keeps the reward in the position
+        let context = get_context(accounts(1), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let before = contract.get_stake_info(accounts(1)).unwrap();
+        contract.unstake_in_pool(DEFAULT_POOL_ID, Some(U128(400_000)));
+        let after = contract.get_stake_info(accounts(1)).unwrap();
+        assert_eq!(after.amount, 600_000);
+        assert_eq!(after.accumulated_reward, before.accumulated_reward);
+
+        // A full unstake returns the principal and freezes the reward
+        let context = get_context(accounts(2), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let reward = contract
+            .get_stake_info(accounts(2))
+            .unwrap()
+            .accumulated_reward;
+        contract.unstake();
+        assert!(contract.get_stake_info(accounts(2)).is_none());
+        assert_eq!(contract.get_total_claimed_reward(), 0);
+        assert_eq!(contract.get_frozen_reward(accounts(2), None), reward);
+
+        // Once claims resume the account withdraws it into its pending payouts
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.pause_claim(false, None);
+        let context = get_context(accounts(2), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        assert_eq!(contract.withdraw_frozen_reward(None), U128(reward));
+        assert_eq!(contract.get_pending_payout(accounts(2), None), reward);
+        assert_eq!(contract.get_total_claimed_reward(), reward);
+    }
+
+    #[test]
+    fn test_rejected_unstake_after_emergency_withdraw() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let before = contract.get_stake_info(sender_id.clone()).unwrap();
+        contract.unstake_in_pool(DEFAULT_POOL_ID, Some(U128(400_000)));
+        let reward = contract.get_total_claimed_reward();
+        assert_eq!(reward, before.accumulated_reward);
+
+        // The rest of the position leaves before the storage check comes back
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.set_emergency_mode(true);
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.emergency_withdraw(None);
+        let result = contract.on_storage_balance_checked(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            StakeInfo {
+                amount: 1_000_000,
+                accumulated_reward: reward,
+                first_stake_time: 0,
+                start_time: 5 * WEEK,
+            },
+            UnstakePayout {
+                principal: 400_000,
+                withdrawn: reward,
+                reward,
+                fee: 0,
+                vested: 0,
+                frozen: 0,
+                total: 400_000 + reward,
+            },
+            Ok(None),
+        );
+
+        // The principal is not put back, the unstake is owed as a pending payout
+        assert!(matches!(result, PromiseOrValue::Value(false)));
+        assert!(contract.get_stake_info(sender_id.clone()).is_none());
+        assert_eq!(contract.get_total_stake(), 0);
+        assert_eq!(contract.get_total_claimed_reward(), reward);
+        assert_eq!(
+            contract.get_pending_payout(sender_id, None),
+            400_000 + reward
+        );
+        assert!(contract.get_stuck_accounts(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_unstake_sponsors_storage_registration() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_storage_registration_mode(StorageRegistrationMode::Sponsor);
+        let context = get_context(accounts(0), storage::DEFAULT_STORAGE_DEPOSIT, 0);
+        testing_env!(context.build());
+        contract.fund_storage_budget();
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        let context = get_context(sender_id.clone(), 1, 0);
+        testing_env!(context.build());
+        let stake_info = contract
+            .load_pool(DEFAULT_POOL_ID)
+            .stakes
+            .get(&sender_id)
+            .unwrap();
+        contract.unstake();
+        let result = contract.on_storage_balance_checked(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            stake_info,
+            UnstakePayout {
+                principal: 1_000_000,
+                withdrawn: 0,
+                reward: 0,
+                fee: 0,
+                vested: 0,
+                frozen: 0,
+                total: 1_000_000,
+            },
+            Ok(None),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(contract.get_stake_info(sender_id.clone()).is_none());
+        assert_eq!(contract.storage_budget, 0);
+
+        // A failed registration returns the deposit to the budget and the payout is pending
+        let result = contract.on_storage_sponsored(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            1_000_000,
+            storage::DEFAULT_STORAGE_DEPOSIT,
+            Err(near_sdk::PromiseError::Failed),
+        );
+        assert!(matches!(result, PromiseOrValue::Value(false)));
+        assert_eq!(contract.storage_budget, storage::DEFAULT_STORAGE_DEPOSIT);
+        assert_eq!(contract.get_pending_payout(sender_id, None), 1_000_000);
+    }
+
+    #[test]
+    fn test_migrate_recomputes_liability_in_batches() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+        let mut staked_balances = UnorderedMap::new(b"s".to_vec());
+        for (index, account_id) in [accounts(1), accounts(2)].iter().enumerate() {
+            staked_balances.insert(
+                account_id,
+                &StakeInfo {
+                    amount: 1_000_000,
+                    accumulated_reward: 100 * (index as u128 + 1),
+                    first_stake_time: 0,
+                    start_time: 0,
+                },
+            );
+        }
+        env::state_write(&StakingContractV1 {
+            owner_id: accounts(0),
+            token_contract: TOKEN_CONTRACT.parse().unwrap(),
+            staked_balances,
+            user_states: UnorderedMap::new(b"user_states".to_vec()),
+            stake_start_time: 0,
+            lock_duration: 2 * WEEK,
+            stake_paused: false,
+            stake_end_time: 0,
+            total_staked: 2_000_000,
+            total_claimed_reward: 0,
+            total_reward: 1_000_000_000,
+        });
+
+        // Nothing is counted by the migration itself
+        let context = get_context(accounts(0), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let mut contract = StakingContract::migrate(1);
+        assert_eq!(
+            contract.load_pool(DEFAULT_POOL_ID).accrued_reward_liability,
+            0
+        );
+        assert_eq!(contract.recompute_liability(1), 1);
+        assert_eq!(contract.recompute_liability(1), 0);
+        assert!(contract.liability_recompute.is_none());
+
+        // Once every position is counted, the liability covers what the positions are owed
+        let context = get_context(accounts(0), 1, 2 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let owed: u128 = [accounts(1), accounts(2)]
+            .into_iter()
+            .map(|account_id| {
+                contract
+                    .get_stake_info(account_id)
+                    .unwrap()
+                    .accumulated_reward
+            })
+            .sum();
+        let liability = contract.get_accrued_reward_liability(None);
+        assert!(liability >= owed && liability <= owed + 2);
+    }
+
+    #[test]
+    fn test_emergency_withdraw_during_liability_recompute() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+        let mut staked_balances = UnorderedMap::new(b"s".to_vec());
+        for account_id in [accounts(1), accounts(2), accounts(3)] {
+            staked_balances.insert(
+                &account_id,
+                &StakeInfo {
+                    amount: 1_000_000,
+                    accumulated_reward: 100,
+                    first_stake_time: 0,
+                    start_time: 0,
+                },
+            );
+        }
+        env::state_write(&StakingContractV1 {
+            owner_id: accounts(0),
+            token_contract: TOKEN_CONTRACT.parse().unwrap(),
+            staked_balances,
+            user_states: UnorderedMap::new(b"user_states".to_vec()),
+            stake_start_time: 0,
+            lock_duration: 2 * WEEK,
+            stake_paused: false,
+            stake_end_time: 0,
+            total_staked: 3_000_000,
+            total_claimed_reward: 0,
+            total_reward: 1_000_000_000,
+        });
+        let context = get_context(accounts(0), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let mut contract = StakingContract::migrate(1);
+        contract.set_emergency_mode(true);
+
+        // The last position is counted, then the first one leaves and the last takes its slot
+        assert_eq!(contract.recompute_liability(1), 2);
+        let context = get_context(accounts(1), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.emergency_withdraw(None);
+        let context = get_context(accounts(0), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        assert_eq!(contract.recompute_liability(10), 0);
+
+        // Each remaining position is counted once
+        let owed: u128 = [accounts(2), accounts(3)]
+            .into_iter()
+            .map(|account_id| {
+                contract
+                    .get_stake_info(account_id)
+                    .unwrap()
+                    .accumulated_reward
+            })
+            .sum();
+        let liability = contract.get_accrued_reward_liability(None);
+        // Every position counted, including the one that left, rounds up by at most one
+        assert!(liability >= owed && liability <= owed + 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Liability recompute in progress")]
+    fn test_migrate_blocks_positions_until_recomputed() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+        let mut staked_balances = UnorderedMap::new(b"s".to_vec());
+        staked_balances.insert(
+            &accounts(1),
+            &StakeInfo {
+                amount: 1_000_000,
+                accumulated_reward: 0,
+                first_stake_time: 0,
+                start_time: 0,
+            },
+        );
+        env::state_write(&StakingContractV1 {
+            owner_id: accounts(0),
+            token_contract: TOKEN_CONTRACT.parse().unwrap(),
+            staked_balances,
+            user_states: UnorderedMap::new(b"user_states".to_vec()),
+            stake_start_time: 0,
+            lock_duration: 2 * WEEK,
+            stake_paused: false,
+            stake_end_time: 0,
+            total_staked: 1_000_000,
+            total_claimed_reward: 0,
+            total_reward: 1_000_000_000,
+        });
+        let mut contract = StakingContract::migrate(1);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(2), U128(1_000_000), "".to_string());
+    }
+
+    #[test]
+    fn test_accrued_reward_liability() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000_000u128),
+            None,
+        );
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(2), U128(3_000_000), "".to_string());
+
+        // The global figure matches the sum of the positions
+        let context = get_context(accounts(1), 1, 6 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let reward_1 = contract
+            .get_stake_info(accounts(1))
+            .unwrap()
+            .accumulated_reward;
+        let reward_2 = contract
+            .get_stake_info(accounts(2))
+            .unwrap()
+            .accumulated_reward;
+        let liability = contract.get_accrued_reward_liability(None);
+        assert!(liability >= reward_1 + reward_2);
+        assert!(liability - (reward_1 + reward_2) <= 2);
+        assert_eq!(
+            contract.load_pool(DEFAULT_POOL_ID).owed_balance(),
+            4_000_000 + std::cmp::min(liability, 1_000_000_000)
+        );
+
+        // Paid rewards are no longer owed
+        contract.unstake();
+        assert!(contract.get_accrued_reward_liability(None) - reward_2 <= 2);
+        assert_eq!(contract.get_total_claimed_reward(), reward_1);
+    }
+
+    #[test]
+    fn test_pro_rata_haircut() {
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+
+        // Budget far below what two stakers accrue in a year
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(100_000),
+            None,
+        );
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        contract.ft_on_transfer(accounts(2), U128(3_000_000), "".to_string());
+
+        let timestamp = 365 * 24 * 60 * 60 * NANOSECONDS;
+        let context = get_context(accounts(1), 1, timestamp);
+        testing_env!(context.build());
+        let owed_1 = contract
+            .get_stake_info(accounts(1))
+            .unwrap()
+            .accumulated_reward;
+        let owed_2 = contract
+            .get_stake_info(accounts(2))
+            .unwrap()
+            .accumulated_reward;
+        let factor = contract.get_haircut_factor(None);
+        assert!(factor < AAR_BASE);
+
+        contract.unstake();
+        let claimed_1 = contract.get_total_claimed_reward();
+        assert_eq!(claimed_1, owed_1 * 100_000 / (owed_1 + owed_2));
+
+        // The second staker gets the same share instead of nothing
+        let context = get_context(accounts(2), 1, timestamp);
+        testing_env!(context.build());
+        assert_eq!(contract.get_haircut_factor(None), factor);
+        contract.unstake();
+        let claimed_2 = contract.get_total_claimed_reward() - claimed_1;
+        assert!(claimed_2 * owed_1 / owed_2 + 1 >= claimed_1);
+        assert!(contract.get_total_claimed_reward() <= 100_000);
+    }
+
+    #[test]
+    fn test_partner_reward_token() {
+        let partner: AccountId = "partner.testnet".parse().unwrap();
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        // 10 tokens per second for 1000 seconds
+        let schedule: Vec<reward_tokens::RatePeriod> = serde_json::from_value(json!([
+            { "start_time": 0, "end_time": 1000, "reward_per_second": "10" }
+        ]))
+        .unwrap();
+        contract.add_reward_token(partner.clone(), schedule, None);
+
+        // Fund 6000, less than the 10000 the schedule would emit
+        let context = get_context(partner.clone(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(6_000), "".to_string());
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        contract.ft_on_transfer(accounts(2), U128(3_000_000), "".to_string());
+
+        let context = get_context(accounts(1), 1, 500 * NANOSECONDS);
+        testing_env!(context.build());
+        let unclaimed = contract.get_unclaimed_rewards(accounts(1), None);
+        assert_eq!(unclaimed, vec![(partner.clone(), 1_250)]);
+        contract.claim(partner.clone(), None);
+        assert!(contract.get_unclaimed_rewards(accounts(1), None)[0].1 == 0);
+
+        // Emission stops once the budget is spent
+        let context = get_context(accounts(2), 1, 1000 * NANOSECONDS);
+        testing_env!(context.build());
+        assert_eq!(
+            contract.get_unclaimed_rewards(accounts(2), None)[0].1,
+            4_500
+        );
+    }
+
+    #[test]
+    fn test_sweep_reward_token() {
+        let partner: AccountId = "partner.testnet".parse().unwrap();
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        // 10 tokens per second for 1000 seconds, funded with 15000
+        let schedule: Vec<reward_tokens::RatePeriod> = serde_json::from_value(json!([
+            { "start_time": 0, "end_time": 1000, "reward_per_second": "10" }
+        ]))
+        .unwrap();
+        contract.add_reward_token(partner.clone(), schedule, None);
+        let context = get_context(partner.clone(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(15_000), "".to_string());
+
+        // Nothing is emitted before the first stake
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 200 * NANOSECONDS);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, 1000 * NANOSECONDS);
+        testing_env!(context.build());
+        contract.sweep_reward_token(partner.clone(), None);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.reward_token_reserve(&partner), 8_000);
+        assert_eq!(
+            contract.get_unclaimed_rewards(accounts(1), None)[0].1,
+            8_000
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Reward schedule has not ended")]
+    fn test_sweep_reward_token_waits_for_schedule() {
+        let partner: AccountId = "partner.testnet".parse().unwrap();
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let schedule: Vec<reward_tokens::RatePeriod> = serde_json::from_value(json!([
+            { "start_time": 0, "end_time": 1000, "reward_per_second": "10" }
+        ]))
+        .unwrap();
+        contract.add_reward_token(partner.clone(), schedule, None);
+
+        let context = get_context(accounts(0), 1, 999 * NANOSECONDS);
+        testing_env!(context.build());
+        contract.sweep_reward_token(partner, None);
+    }
+
+    #[test]
+    fn test_multiple_pools() {
+        let other_token: AccountId = "other.testnet".parse().unwrap();
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        // Flat 10% AAR without lock
+        let pool_id = contract.create_pool(other_token.clone(), U128(1_000_000), 0, vec![], 1000);
+        assert_eq!(pool_id, 1);
+        assert_eq!(contract.get_pools(None, None).len(), 2);
+
+        // The msg selects the pool, an empty msg the default pool
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        let context = get_context(other_token.clone(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(
+            accounts(1),
+            U128(2_000_000),
+            json!({ "pool_id": pool_id }).to_string(),
+        );
+        assert_eq!(contract.get_total_stake(), 1_000_000);
+        assert_eq!(contract.get_total_stake_in_pool(pool_id), 2_000_000);
+
+        // Each pool has its own rates and pause switches
+        let timestamp = 365 * 24 * 60 * 60 * NANOSECONDS;
+        let context = get_context(accounts(0), 1, timestamp);
+        testing_env!(context.build());
+        contract.pause_stake(true, Some(pool_id));
+        assert!(!contract.get_pause_status(None).stake);
+        let stake_info = contract
+            .get_stake_info_in_pool(accounts(1), pool_id)
+            .unwrap();
+        assert_eq!(stake_info.accumulated_reward, 200_000);
+
+        let context = get_context(accounts(1), 1, timestamp);
+        testing_env!(context.build());
+        contract.unstake_in_pool(pool_id, None);
+        assert_eq!(contract.get_total_stake_in_pool(pool_id), 0);
+        assert_eq!(contract.get_total_claimed_reward_in_pool(pool_id), 200_000);
+        assert_eq!(contract.get_total_stake(), 1_000_000);
+    }
+
+    #[test]
+    fn test_pool_rejects_other_token() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let pool_id = contract.create_pool(
+            "other.testnet".parse().unwrap(),
+            U128(1_000_000),
+            0,
+            vec![],
+            AAR,
+        );
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(
+            accounts(1),
+            U128(1_000_000),
+            json!({ "pool_id": pool_id }).to_string(),
+        );
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000_000))));
+        assert!(near_sdk::test_utils::get_logs()[0].contains("\"reason\":\"wrong_token\""));
+        assert_eq!(contract.get_total_stake_in_pool(pool_id), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "End time should gt start time.")]
+    fn test_end_time_cannot_precede_start_time() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            Some(2 * WEEK),
+        );
+        contract.set_stake_end_time(WEEK, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Season cannot end after the next one starts")]
+    fn test_season_end_cannot_pass_next_start() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_end_time(WEEK, None);
+        contract.add_season(
+            DEFAULT_POOL_ID,
+            WEEK,
+            3 * WEEK,
+            vec![],
+            1000,
+            U128(1_000_000),
+        );
+        contract.set_stake_end_time(2 * WEEK, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Below minimum stake")]
+    fn test_rollover_respects_min_stake() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_end_time(WEEK, None);
+        let season_id = contract.add_season(
+            DEFAULT_POOL_ID,
+            WEEK,
+            3 * WEEK,
+            vec![],
+            1000,
+            U128(1_000_000),
+        );
+        contract.set_min_stake(U128(10_000), Some(season_id));
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000), "".to_string());
+
+        let context = get_context(accounts(1), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.rollover(None);
+    }
+
+    #[test]
+    fn test_season_rollover() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_end_time(WEEK, None);
+        let season_id = contract.add_season(
+            DEFAULT_POOL_ID,
+            WEEK,
+            3 * WEEK,
+            vec![],
+            1000,
+            U128(1_000_000),
+        );
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        contract.ft_on_transfer(accounts(2), U128(1_000_000), "".to_string());
+
+        // At the boundary one staker rolls over, the other exits without the lock penalty
+        let context = get_context(accounts(1), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let reward = contract
+            .get_stake_info(accounts(1))
+            .unwrap()
+            .accumulated_reward;
+        assert!(reward > 0);
+        contract.rollover(None);
+        let rolled = contract
+            .get_stake_info_in_pool(accounts(1), season_id)
+            .unwrap();
+        assert_eq!(rolled.amount, 1_000_000 + reward);
+
+        let context = get_context(accounts(2), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+        assert_eq!(contract.get_total_claimed_reward(), 2 * reward);
+        assert_eq!(contract.get_total_stake(), 0);
+
+        // The next season accrues at its own rate until its end
+        let context = get_context(accounts(1), 1, 4 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let season_reward =
+            (1_000_000 + reward) * 1000 * 2 * WEEK as u128 / (SECONDS_IN_A_YEAR * AAR_BASE);
+        let seasons =
+            serde_json::to_value(contract.get_seasons(Some(season_id), Some(accounts(1)))).unwrap();
+        assert_eq!(seasons.as_array().unwrap().len(), 2);
+        assert_eq!(
+            seasons[0]["account_rewards_earned"],
+            json!(reward.to_string())
+        );
+        assert_eq!(
+            seasons[1]["account_rewards_earned"],
+            json!(season_reward.to_string())
+        );
+        assert_eq!(
+            seasons[1]["rewards_earned"],
+            json!(season_reward.to_string())
+        );
+    }
+
+    #[test]
+    fn test_scheduled_start_time() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            Some(3 * WEEK),
+        );
+        assert_eq!(
+            contract.load_pool(DEFAULT_POOL_ID).stake_start_time,
+            3 * WEEK
+        );
+        contract.set_stake_start_time(WEEK, None);
+
+        // Stakes before the start accrue nothing until then
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        let context = get_context(accounts(1), 0, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let stake_info = contract.get_stake_info(accounts(1)).unwrap();
+        assert_eq!(stake_info.accumulated_reward, 0);
+        assert_eq!(stake_info.first_stake_time, WEEK);
+
+        // The first boost week starts at the scheduled start
+        let context = get_context(accounts(1), 0, 2 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let expected_rewards =
+            (1_000_000 * AAR_EARLY[0] * WEEK as u128) / (SECONDS_IN_A_YEAR * AAR_BASE);
+        assert_eq!(
+            contract
+                .get_stake_info(accounts(1))
+                .unwrap()
+                .accumulated_reward,
+            expected_rewards
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Stake already started.")]
+    fn test_cannot_reschedule_started_stake() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.set_stake_start_time(2 * WEEK, None);
+    }
+
+    #[test]
+    fn test_stake_caps_refund_excess() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_caps(Some(U128(1_000)), Some(U128(1_500)), None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(1), U128(1_200), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(200))));
+        let refund = contract.ft_on_transfer(accounts(2), U128(800), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(300))));
+        assert_eq!(contract.get_total_stake(), 1_500);
+
+        // A full pool refunds the whole transfer without creating a position
+        let refund = contract.ft_on_transfer(accounts(3), U128(100), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(100))));
+        assert!(contract.get_stake_info(accounts(3)).is_none());
+    }
+
+    #[test]
+    fn test_min_stake_and_partial_unstake() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_min_stake(U128(1_000), None);
+
+        // A transfer below the minimum is refunded without creating a position
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(1), U128(999), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(999))));
+        assert!(contract.get_stake_info(accounts(1)).is_none());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        // Within the lock a partial unstake returns principal only and keeps the reward
+        let unstake_time = 12_345;
+        let context = get_context(accounts(1), 1, unstake_time * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake_in_pool(DEFAULT_POOL_ID, Some(U128(400_000)));
+        let stake_info = serde_json::to_value(contract.get_stake_info(accounts(1))).unwrap();
+        assert_eq!(stake_info["amount"], json!(600_000));
+        assert_eq!(contract.get_total_stake(), 600_000);
+
+        // Settled rewards and rounding remainders add up to the exact accrual
+        let pool = serde_json::to_value(contract.get_pool(DEFAULT_POOL_ID).unwrap()).unwrap();
+        let settled: u128 = pool["total_settled_reward"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let remainder: u128 = pool["reward_remainder"].as_str().unwrap().parse().unwrap();
+        assert_eq!(
+            settled * SECONDS_IN_A_YEAR * AAR_BASE + remainder,
+            1_000_000 * AAR_EARLY[0] * unstake_time as u128
+        );
+        assert!(remainder > 0);
+        assert_eq!(stake_info["accumulated_reward"], json!(settled));
+    }
+
+    #[test]
+    #[should_panic(expected = "Remaining stake below min stake")]
+    fn test_partial_unstake_rejects_dust() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_min_stake(U128(1_000), None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_500), "".to_string());
+
+        let context = get_context(accounts(1), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake_in_pool(DEFAULT_POOL_ID, Some(U128(1_000)));
+    }
+
+    #[test]
+    fn test_allowlist_mode_refunds_unlisted() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_access_mode(AccessMode::Allowlist);
+        contract.add_to_allowlist(vec![accounts(1), accounts(2)]);
+        contract.remove_from_allowlist(vec![accounts(2)]);
+        assert_eq!(contract.get_allowlist(None, None), vec![accounts(1)]);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(2), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        assert!(contract.get_stake_info(accounts(2)).is_none());
+        let refund = contract.ft_on_transfer(accounts(1), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+        assert_eq!(contract.get_total_stake(), 1_000);
+    }
+
+    #[test]
+    fn test_denylist_withdraws_principal_only() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_guardian(Some(accounts(4)));
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        // The guardian blocks the account, further stakes are refunded
+        let context = get_context(accounts(4), 1, 0);
+        testing_env!(context.build());
+        contract.add_to_denylist(vec![accounts(1)]);
+        assert!(contract.is_denylisted(accounts(1)));
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(1), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+
+        // After the lock only the principal is returned, the reward is frozen
+        let context = get_context(accounts(1), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+        assert!(contract.get_stake_info(accounts(1)).is_none());
+        assert_eq!(contract.get_total_stake(), 0);
+        assert_eq!(contract.get_total_claimed_reward(), 0);
+        let reward = contract
+            .load_pool(DEFAULT_POOL_ID)
+            .calculate_reward(1_000_000, 5 * WEEK, 0);
+        assert_eq!(contract.get_frozen_reward(accounts(1), None), reward);
+        assert_eq!(contract.get_accrued_reward_liability(None), reward);
+
+        // Removing the account from the denylist pays the reward as a pending payout
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.remove_from_denylist(vec![accounts(1)]);
+        assert_eq!(contract.get_frozen_reward(accounts(1), None), 0);
+        assert_eq!(contract.get_pending_payout(accounts(1), None), reward);
+        assert_eq!(contract.get_total_claimed_reward(), reward);
+        assert_eq!(contract.get_accrued_reward_liability(None), 0);
+    }
+
+    #[test]
+    fn test_rejected_transfer_is_refunded_with_reason() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.pause_stake(true, None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(1), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        let event = near_sdk::test_utils::get_logs().pop().unwrap();
+        let event: serde_json::Value =
+            serde_json::from_str(event.strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        assert_eq!(event["event"], json!("stake_refunded"));
+        assert_eq!(
+            event["data"],
+            json!({
+                "sender_id": accounts(1),
+                "pool_id": 0,
+                "amount": "1000",
+                "reason": "stake_paused",
+            })
+        );
+
+        // An invalid msg or unknown pool is refunded as well
+        let refund = contract.ft_on_transfer(accounts(1), U128(1_000), "stake".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        let refund = contract.ft_on_transfer(
+            accounts(1),
+            U128(1_000),
+            json!({ "pool_id": 7 }).to_string(),
+        );
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        assert!(contract.get_stake_info(accounts(1)).is_none());
+    }
+
+    #[test]
+    fn test_referral_reward() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_referral_config(1_000, ReferralFunding::Budget, None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(
+            accounts(0),
+            U128(50),
+            json!({ "referral_budget": true }).to_string(),
+        );
+        contract.ft_on_transfer(
+            sender_id.clone(),
+            U128(1_000_000),
+            json!({ "referrer": accounts(2) }).to_string(),
+        );
+        // The first referrer is permanent
+        contract.ft_on_transfer(
+            sender_id.clone(),
+            U128(1_000),
+            json!({ "referrer": accounts(3) }).to_string(),
+        );
+        assert_eq!(contract.get_referrer(sender_id.clone()), Some(accounts(2)));
+        let stats = serde_json::to_value(contract.get_referral_stats(accounts(2), None)).unwrap();
+        assert_eq!(stats["referral_count"], json!(1));
+
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+        let reward_amount = contract.get_total_claimed_reward();
+        assert!(reward_amount > 500);
+        contract.on_storage_balance_checked(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            StakeInfo {
+                amount: 1_001_000,
+                accumulated_reward: reward_amount,
+                first_stake_time: 0,
+                start_time: 5 * WEEK,
+            },
+            UnstakePayout {
+                principal: 1_001_000,
+                withdrawn: reward_amount,
+                reward: reward_amount,
+                fee: 0,
+                vested: 0,
+                frozen: 0,
+                total: 1_001_000 + reward_amount,
+            },
+            Ok(Some(StorageBalance {
+                total: NearToken::from_yoctonear(1),
+                available: NearToken::from_yoctonear(0),
+            })),
+        );
+
+        // 10% of the reward, capped by the referral budget
+        let stats = serde_json::to_value(contract.get_referral_stats(accounts(2), None)).unwrap();
+        assert_eq!(stats["earned"], json!("50"));
+        assert_eq!(contract.get_pending_payout(accounts(2), None), 50);
+        let config = serde_json::to_value(contract.get_referral_config(None)).unwrap();
+        assert_eq!(config["budget"], json!("0"));
+    }
+
+    #[test]
+    fn test_loyalty_tiers() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let config: loyalty::LoyaltyConfig = serde_json::from_value(json!({
+            "min_amount": "1000",
+            "grace_period": WEEK,
+            "tiers": [
+                { "min_duration": WEEK, "multiplier_bps": 11_000 },
+                { "min_duration": 2 * WEEK, "multiplier_bps": 12_500 },
+                { "min_duration": 4 * WEEK, "multiplier_bps": 15_000 },
+            ],
+        }))
+        .unwrap();
+        contract.set_loyalty_config(Some(config), None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+        assert_eq!(
+            contract.get_loyalty_tier(sender_id.clone(), None),
+            loyalty::LoyaltyTier::None
+        );
+
+        // Each week pays the multiplier of the tier held during it: none, Bronze, then Silver
+        let context = get_context(sender_id.clone(), 1, 3 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        assert_eq!(
+            contract.get_loyalty_tier(sender_id.clone(), None),
+            loyalty::LoyaltyTier::Silver
+        );
+        contract.unstake();
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        let base = pool.calculate_reward(1_000_000, 3 * WEEK, 0);
+        let bronze = pool.calculate_reward(1_000_000, 2 * WEEK, WEEK);
+        let silver = pool.calculate_reward(1_000_000, 3 * WEEK, 2 * WEEK);
+        assert_eq!(
+            contract.get_total_claimed_reward(),
+            base + bronze * 1_000 / 10_000 + silver * 2_500 / 10_000
+        );
+        assert_eq!(
+            contract.get_loyalty_tier(sender_id.clone(), None),
+            loyalty::LoyaltyTier::None
+        );
+
+        // Restaking within the grace period keeps the history, the gap does not count
+        let restake_time = 3 * WEEK + 3 * 24 * 60 * 60;
+        let context = get_context(
+            TOKEN_CONTRACT.parse().unwrap(),
+            0,
+            restake_time * NANOSECONDS,
+        );
+        testing_env!(context.build());
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000), "".to_string());
+        let context = get_context(sender_id.clone(), 1, (restake_time + WEEK) * NANOSECONDS);
+        testing_env!(context.build());
+        assert_eq!(
+            contract.get_loyalty_tier(sender_id.clone(), None),
+            loyalty::LoyaltyTier::Gold
+        );
+
+        // A lapse longer than the grace period starts over
+        contract.unstake();
+        let context = get_context(
+            TOKEN_CONTRACT.parse().unwrap(),
+            0,
+            (restake_time + 3 * WEEK) * NANOSECONDS,
+        );
+        testing_env!(context.build());
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000), "".to_string());
+        assert_eq!(
+            contract.get_loyalty_tier(sender_id, None),
+            loyalty::LoyaltyTier::None
+        );
+    }
+
+    #[test]
+    fn test_nft_boost() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let collection_id: AccountId = "nft.testnet".parse().unwrap();
+        contract.add_boost_collection(collection_id.clone(), 15_000);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        // Another account's NFT does not count
+        assert!(!contract.on_nft_verified(
+            sender_id.clone(),
+            collection_id.clone(),
+            "1".to_string(),
+            NFT_BOOST_STORAGE_DEPOSIT,
+            Ok(Some(near_contract_standards::non_fungible_token::Token {
+                token_id: "1".to_string(),
+                owner_id: accounts(2),
+                metadata: None,
+                approved_account_ids: None,
+            })),
+        ));
+        assert!(contract.get_nft_boost(sender_id.clone()).is_none());
+
+        // A deposited NFT boosts the reward, a second one is sent back
+        let context = get_context(collection_id.clone(), 0, 0);
+        testing_env!(context.build());
+        let returned = contract.nft_on_transfer(
+            sender_id.clone(),
+            sender_id.clone(),
+            "2".to_string(),
+            "".to_string(),
+        );
+        assert!(matches!(returned, PromiseOrValue::Value(false)));
+        let returned = contract.nft_on_transfer(
+            sender_id.clone(),
+            sender_id.clone(),
+            "3".to_string(),
+            "".to_string(),
+        );
+        assert!(matches!(returned, PromiseOrValue::Value(true)));
+
+        let context = get_context(sender_id.clone(), 1, 3 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+        let base = contract
+            .load_pool(DEFAULT_POOL_ID)
+            .calculate_reward(1_000_000, 3 * WEEK, 0);
+        assert_eq!(contract.get_total_claimed_reward(), base + base / 2);
+    }
+
+    #[test]
+    fn test_nft_boost_follows_token_holder() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let collection_id: AccountId = "nft.testnet".parse().unwrap();
+        contract.add_boost_collection(collection_id.clone(), 15_000);
+        let token = |owner_id: AccountId| {
+            Ok(Some(near_contract_standards::non_fungible_token::Token {
+                token_id: "1".to_string(),
+                owner_id,
+                metadata: None,
+                approved_account_ids: None,
+            }))
+        };
+
+        // The NFT is verified by one account, then moves to another one that verifies it
+        assert!(contract.on_nft_verified(
+            accounts(1),
+            collection_id.clone(),
+            "1".to_string(),
+            NFT_BOOST_STORAGE_DEPOSIT,
+            token(accounts(1)),
+        ));
+        assert!(contract.on_nft_verified(
+            accounts(2),
+            collection_id.clone(),
+            "1".to_string(),
+            NFT_BOOST_STORAGE_DEPOSIT,
+            token(accounts(2)),
+        ));
+        assert!(contract.get_nft_boost(accounts(1)).is_none());
+        assert!(contract.get_nft_boost(accounts(2)).is_some());
+
+        // Depositing the NFT moves the boost again
+        let context = get_context(collection_id.clone(), 0, 0);
+        testing_env!(context.build());
+        contract.nft_on_transfer(accounts(3), accounts(3), "1".to_string(), "".to_string());
+        assert!(contract.get_nft_boost(accounts(2)).is_none());
+        assert!(contract.get_nft_boost(accounts(3)).is_some());
+    }
+
+    #[test]
+    fn test_nft_boost_settles_tracked_pools_and_refunds_deposit() {
+        let other_token: AccountId = "other.testnet".parse().unwrap();
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let pool_id = contract.create_pool(other_token.clone(), U128(1_000_000), 0, vec![], 1000);
+        let collection_id: AccountId = "nft.testnet".parse().unwrap();
+        contract.add_boost_collection(collection_id.clone(), 15_000);
+        let token = |owner_id: AccountId| {
+            Ok(Some(near_contract_standards::non_fungible_token::Token {
+                token_id: "1".to_string(),
+                owner_id,
+                metadata: None,
+                approved_account_ids: None,
+            }))
+        };
+
+        let context = get_context(other_token.clone(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(
+            accounts(1),
+            U128(2_000_000),
+            json!({ "pool_id": pool_id }).to_string(),
+        );
+        assert_eq!(
+            contract.account_pools.get(&accounts(1)),
+            Some(vec![pool_id])
+        );
+
+        // The position is settled when the boost starts half way, a second verification
+        // keeps the storage deposit already paid
+        let half_year = 365 * 24 * 60 * 60 / 2;
+        let context = get_context(accounts(0), 0, half_year * NANOSECONDS);
+        testing_env!(context.build());
+        assert!(contract.on_nft_verified(
+            accounts(1),
+            collection_id.clone(),
+            "1".to_string(),
+            NFT_BOOST_STORAGE_DEPOSIT,
+            token(accounts(1)),
+        ));
+        assert!(contract.on_nft_verified(
+            accounts(1),
+            collection_id.clone(),
+            "1".to_string(),
+            NFT_BOOST_STORAGE_DEPOSIT,
+            token(accounts(1)),
+        ));
+        assert_eq!(
+            contract.nft_storage_deposits.get(&accounts(1)),
+            Some(NFT_BOOST_STORAGE_DEPOSIT)
+        );
+
+        let context = get_context(accounts(1), 1, 2 * half_year * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake_in_pool(pool_id, None);
+        assert_eq!(
+            contract.get_total_claimed_reward_in_pool(pool_id),
+            100_000 + 150_000
+        );
+        assert!(contract.account_pools.get(&accounts(1)).is_none());
+
+        // Dropping the boost refunds the deposit
+        let context = get_context(accounts(0), 0, 2 * half_year * NANOSECONDS);
+        testing_env!(context.build());
+        assert!(!contract.on_nft_refreshed(accounts(1), token(accounts(2))));
+        assert!(contract.get_nft_boost(accounts(1)).is_none());
+        assert!(contract.nft_storage_deposits.get(&accounts(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach NFT_BOOST_STORAGE_DEPOSIT to cover storage")]
+    fn test_verify_nft_requires_storage_deposit() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let collection_id: AccountId = "nft.testnet".parse().unwrap();
+        contract.add_boost_collection(collection_id.clone(), 15_000);
+
+        let context = get_context(accounts(1), 0, 0);
+        testing_env!(context.build());
+        contract.verify_nft(collection_id, "1".to_string());
+    }
+
+    #[test]
+    fn test_protocol_fee() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_protocol_fee(1_000, None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let before = contract.get_stake_info(sender_id.clone()).unwrap();
+        let fee = contract.protocol_fee(before.accumulated_reward);
+        assert_eq!(fee, before.accumulated_reward / 10);
+        contract.unstake();
+        contract.on_storage_balance_checked(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            before,
+            UnstakePayout {
+                principal: 1_000_000,
+                withdrawn: contract.get_total_claimed_reward(),
+                reward: contract.get_total_claimed_reward(),
+                fee,
+                vested: 0,
+                frozen: 0,
+                total: 0,
+            },
+            Ok(Some(StorageBalance {
+                total: NearToken::from_yoctonear(1),
+                available: NearToken::from_yoctonear(0),
+            })),
+        );
+        let fees = serde_json::to_value(contract.get_fees()).unwrap();
+        assert_eq!(
+            fees,
+            json!([{
+                "token_id": TOKEN_CONTRACT,
+                "uncollected": fee.to_string(),
+                "total": fee.to_string(),
+            }])
+        );
+
+        // Uncollected fees are sent to the owner when no treasury is set
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.collect_fees(TOKEN_CONTRACT.parse().unwrap());
+        let fees = serde_json::to_value(contract.get_fees()).unwrap();
+        assert_eq!(fees[0]["uncollected"], json!("0"));
+        assert_eq!(fees[0]["total"], json!(fee.to_string()));
+    }
+
+    #[test]
+    fn test_vesting() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let config: vesting::VestingConfig = serde_json::from_value(json!({
+            "cliff": WEEK,
+            "duration": 4 * WEEK,
+        }))
+        .unwrap();
+        contract.set_vesting_config(Some(config), None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        let sender_id = accounts(1);
+        contract.ft_on_transfer(sender_id.clone(), U128(1_000_000), "".to_string());
+
+        // Only the principal is transferred, the reward starts vesting
+        let context = get_context(sender_id.clone(), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let before = contract.get_stake_info(sender_id.clone()).unwrap();
+        let reward = before.accumulated_reward;
+        assert!(reward > 0);
+        contract.unstake();
+        contract.on_storage_balance_checked(
+            DEFAULT_POOL_ID,
+            sender_id.clone(),
+            before,
+            UnstakePayout {
+                principal: 1_000_000,
+                withdrawn: reward,
+                reward,
+                fee: 0,
+                vested: reward,
+                frozen: 0,
+                total: 1_000_000,
+            },
+            Ok(Some(StorageBalance {
+                total: NearToken::from_yoctonear(1),
+                available: NearToken::from_yoctonear(0),
+            })),
+        );
+        let balance =
+            serde_json::to_value(contract.get_vesting_balance(sender_id.clone(), None)).unwrap();
+        assert_eq!(
+            balance,
+            json!({ "vested": "0", "locked": reward.to_string() })
+        );
+        assert_eq!(contract.load_pool(DEFAULT_POOL_ID).total_vesting, reward);
+
+        // Nothing vests before the cliff
+        let context = get_context(sender_id.clone(), 1, (5 * WEEK + WEEK / 2) * NANOSECONDS);
+        testing_env!(context.build());
+        let balance =
+            serde_json::to_value(contract.get_vesting_balance(sender_id.clone(), None)).unwrap();
+        assert_eq!(balance["vested"], json!("0"));
+
+        // Then rewards unlock linearly
+        let context = get_context(sender_id.clone(), 1, 7 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let balance =
+            serde_json::to_value(contract.get_vesting_balance(sender_id.clone(), None)).unwrap();
+        assert_eq!(balance["vested"], json!((reward / 2).to_string()));
+        contract.withdraw_vested(None);
+        let balance =
+            serde_json::to_value(contract.get_vesting_balance(sender_id.clone(), None)).unwrap();
+        assert_eq!(balance["vested"], json!("0"));
+        assert_eq!(balance["locked"], json!((reward - reward / 2).to_string()));
+
+        // Everything is withdrawable once the duration has passed
+        let context = get_context(sender_id.clone(), 1, 9 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.withdraw_vested(None);
+        let balance = serde_json::to_value(contract.get_vesting_balance(sender_id, None)).unwrap();
+        assert_eq!(balance, json!({ "vested": "0", "locked": "0" }));
+        assert_eq!(contract.load_pool(DEFAULT_POOL_ID).total_vesting, 0);
+    }
+
+    #[test]
+    fn test_vesting_grant_keeps_earlier_schedule() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let config: vesting::VestingConfig = serde_json::from_value(json!({
+            "cliff": WEEK,
+            "duration": 4 * WEEK,
+        }))
+        .unwrap();
+        contract.set_vesting_config(Some(config), None);
+        let mut pool = contract.load_pool(DEFAULT_POOL_ID);
+        contract.vest_reward(DEFAULT_POOL_ID, &mut pool, &accounts(1), 4_000);
+
+        // A second grant after two weeks does not restart the first one
+        let context = get_context(accounts(1), 1, 2 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.vest_reward(DEFAULT_POOL_ID, &mut pool, &accounts(1), 4_000);
+        contract.save_pool(DEFAULT_POOL_ID, &pool);
+        let balance =
+            serde_json::to_value(contract.get_vesting_balance(accounts(1), None)).unwrap();
+        assert_eq!(balance, json!({ "vested": "2000", "locked": "6000" }));
+
+        // The first grant is fully vested on its own schedule
+        let context = get_context(accounts(1), 1, 4 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let balance =
+            serde_json::to_value(contract.get_vesting_balance(accounts(1), None)).unwrap();
+        assert_eq!(balance, json!({ "vested": "6000", "locked": "2000" }));
+        contract.withdraw_vested(None);
+        assert_eq!(contract.load_pool(DEFAULT_POOL_ID).total_vesting, 2_000);
+    }
+
+    #[test]
+    fn test_airdrop() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let (root, leaves) = airdrop_tree([(accounts(1), 1_000), (accounts(2), 2_000)]);
+        let reward_id =
+            contract.create_airdrop(root, U128(3_000), airdrop::AirdropTarget::Reward, None);
+        let stake_id =
+            contract.create_airdrop(root, U128(3_000), airdrop::AirdropTarget::Stake, None);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.total_reward, 1_000_000 - 6_000);
+        assert_eq!(pool.airdrop_reserve, 6_000);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        // A reward bonus is added to the accumulated reward of the position
+        let context = get_context(accounts(1), 1, 0);
+        testing_env!(context.build());
+        contract.claim_airdrop(reward_id, 0, U128(1_000), vec![leaves[1].into()]);
+        let stake_info = contract.get_stake_info(accounts(1)).unwrap();
+        assert_eq!(stake_info.accumulated_reward, 1_000);
+        assert_eq!(stake_info.amount, 1_000_000);
+        assert!(contract.is_airdrop_claimed(reward_id, 0));
+        assert!(!contract.is_airdrop_claimed(reward_id, 1));
+        assert!(!contract.is_airdrop_claimed(stake_id, 0));
+
+        // A stake bonus opens a position for an account without one
+        let context = get_context(accounts(2), 1, 0);
+        testing_env!(context.build());
+        contract.claim_airdrop(stake_id, 1, U128(2_000), vec![leaves[0].into()]);
+        let stake_info = contract.get_stake_info(accounts(2)).unwrap();
+        assert_eq!(stake_info.amount, 2_000);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.total_staked, 1_002_000);
+        assert_eq!(pool.total_reward, 1_000_000 - 6_000 + 1_000);
+
+        // Closing returns the unclaimed bonuses to the reward budget
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+        contract.close_airdrop(reward_id);
+        contract.close_airdrop(stake_id);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.airdrop_reserve, 0);
+        assert_eq!(pool.total_reward, 1_000_000 - 2_000);
+        let airdrop = serde_json::to_value(contract.get_airdrop(reward_id)).unwrap();
+        assert_eq!(airdrop["claimed"], json!("1000"));
+        assert_eq!(airdrop["closed"], json!(true));
+    }
+
+    #[test]
+    fn test_airdrop_stake_below_min_stake_is_pending() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_min_stake(U128(5_000), None);
+        let (root, leaves) = airdrop_tree([(accounts(1), 1_000), (accounts(2), 2_000)]);
+        let airdrop_id =
+            contract.create_airdrop(root, U128(3_000), airdrop::AirdropTarget::Stake, None);
+
+        // The bonus cannot open a position below the minimum stake and is owed instead
+        let context = get_context(accounts(1), 1, 0);
+        testing_env!(context.build());
+        contract.claim_airdrop(airdrop_id, 0, U128(1_000), vec![leaves[1].into()]);
+        assert!(contract.get_stake_info(accounts(1)).is_none());
+        assert_eq!(contract.get_pending_payout(accounts(1), None), 1_000);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.total_staked, 0);
+        assert_eq!(pool.total_pending_payout, 1_000);
+        assert_eq!(pool.airdrop_reserve, 2_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid merkle proof")]
+    fn test_airdrop_rejects_wrong_amount() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let (root, leaves) = airdrop_tree([(accounts(1), 1_000), (accounts(2), 2_000)]);
+        let airdrop_id =
+            contract.create_airdrop(root, U128(3_000), airdrop::AirdropTarget::Stake, None);
+
+        let context = get_context(accounts(1), 1, 0);
+        testing_env!(context.build());
+        contract.claim_airdrop(airdrop_id, 0, U128(2_000), vec![leaves[1].into()]);
+    }
+
+    #[test]
+    fn test_seed_stakes() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        let msg = json!({
+            "seed": [
+                {
+                    "account_id": accounts(1),
+                    "amount": "1000000",
+                    "first_stake_time": 0,
+                    "accumulated_reward": "5000",
+                },
+                {
+                    "account_id": accounts(2),
+                    "amount": "2000000",
+                    "first_stake_time": WEEK,
+                    "accumulated_reward": "0",
+                },
+            ]
+        })
+        .to_string();
+
+        // Batches from other accounts or not matching the deposit are refunded
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 2 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(1), U128(3_005_000), msg.clone());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(3_005_000))));
+        let refund = contract.ft_on_transfer(accounts(0), U128(3_000_000), msg.clone());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(3_000_000))));
+        let event = near_sdk::test_utils::get_logs().pop().unwrap();
+        assert!(event.contains("seed_total_mismatch"));
+        assert!(contract.get_stake_info(accounts(1)).is_none());
+
+        let refund = contract.ft_on_transfer(accounts(0), U128(3_005_000), msg.clone());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+        let stake_info = contract.get_stake_info(accounts(1)).unwrap();
+        assert_eq!(stake_info.amount, 1_000_000);
+        assert_eq!(stake_info.accumulated_reward, 5_000);
+        assert_eq!(stake_info.first_stake_time, 0);
+        assert_eq!(stake_info.start_time, 2 * WEEK);
+        let stake_info = contract.get_stake_info(accounts(2)).unwrap();
+        assert_eq!(stake_info.first_stake_time, WEEK);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.total_staked, 3_000_000);
+        assert_eq!(pool.total_reward, 1_005_000);
+        assert_eq!(pool.accrued_reward_liability, 5_000);
+
+        // Existing positions cannot be seeded again
+        let refund = contract.ft_on_transfer(accounts(0), U128(3_005_000), msg);
+        assert!(matches!(refund, PromiseOrValue::Value(U128(3_005_000))));
+        let event = near_sdk::test_utils::get_logs().pop().unwrap();
+        assert!(event.contains("invalid_seed"));
+    }
+
+    #[test]
+    fn test_force_unstake() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_end_time(4 * WEEK, None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        for index in 1..4 {
+            contract.ft_on_transfer(accounts(index), U128(1_000_000), "".to_string());
+        }
+
+        // Positions are settled like an unstake once the pool has ended
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let reward = contract
+            .get_stake_info(accounts(1))
+            .unwrap()
+            .accumulated_reward;
+        assert!(reward > 0);
+        contract.force_unstake(accounts(1), None);
+        assert!(contract.get_stake_info(accounts(1)).is_none());
+        assert_eq!(contract.get_total_claimed_reward(), reward);
+
+        // Ranges are drained from the same index
+        assert_eq!(contract.force_unstake_range(0, 1, None), 1);
+        assert_eq!(contract.force_unstake_range(0, 10, None), 1);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert_eq!(pool.stakes.len(), 0);
+        assert_eq!(pool.total_staked, 0);
+        assert_eq!(contract.get_total_claimed_reward(), 3 * reward);
+        assert_eq!(contract.force_unstake_range(0, 10, None), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pool has not ended")]
+    fn test_force_unstake_requires_ended_pool() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.force_unstake(accounts(1), None);
+    }
+
+    #[test]
+    fn test_finalize_pool() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_end_time(4 * WEEK, None);
+        let partner: AccountId = "partner.testnet".parse().unwrap();
+        contract.add_reward_token(partner.clone(), vec![], None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+        contract.ft_on_transfer(accounts(2), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.force_unstake(accounts(1), None);
+        let owed = contract
+            .get_stake_info(accounts(2))
+            .unwrap()
+            .accumulated_reward;
+
+        // Positions left open hold the close-out until the grace period has passed
+        let context = get_context(
+            accounts(0),
+            1,
+            (4 * WEEK + CLOSE_OUT_GRACE_PERIOD) * NANOSECONDS,
+        );
+        testing_env!(context.build());
+        let close_out = serde_json::to_value(contract.get_close_out(None)).unwrap();
+        let claimed = contract.get_total_claimed_reward();
+        // The liability rounds up against the positions, it is what stays reserved
+        let outstanding = contract.get_accrued_reward_liability(None);
+        assert!(owed <= outstanding && outstanding <= owed + 1);
+        let unused = 1_000_000 - claimed - outstanding;
+        assert_eq!(close_out["open_positions"], json!(1));
+        assert_eq!(close_out["unused_reward"], json!(unused.to_string()));
+        contract.finalize_pool(None);
+        let pool = contract.load_pool(DEFAULT_POOL_ID);
+        assert!(pool.finalized);
+        assert_eq!(pool.swept_reward, unused);
+        assert_eq!(pool.total_reward, claimed + outstanding);
+
+        // Only rewards already owed are paid, the pool takes no new stakes
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 9 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(3), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        // Nor reward token budget
+        let context = get_context(partner.clone(), 0, 9 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        let refund = contract.ft_on_transfer(accounts(0), U128(1_000), "".to_string());
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        let context = get_context(accounts(2), 1, 9 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.unstake();
+        assert_eq!(contract.get_total_claimed_reward(), claimed + owed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Positions are still open")]
+    fn test_finalize_pool_waits_for_positions() {
+        let context = get_context(accounts(0), 1, 0);
+        testing_env!(context.build());
+
+        let mut contract = StakingContract::new(
+            accounts(0),
+            TOKEN_CONTRACT.parse().unwrap(),
+            U128(1_000_000u128),
+            None,
+        );
+        contract.set_stake_end_time(4 * WEEK, None);
+
+        let context = get_context(TOKEN_CONTRACT.parse().unwrap(), 0, 0);
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(1), U128(1_000_000), "".to_string());
+
+        let context = get_context(accounts(0), 1, 5 * WEEK * NANOSECONDS);
+        testing_env!(context.build());
+        contract.finalize_pool(None);
+    }
 }
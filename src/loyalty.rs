@@ -0,0 +1,234 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakeInfo, StakingContract, StakingContractExt, AAR_BASE, NANOSECONDS, U256};
+
+/// Maximum loyalty multiplier (3x)
+pub const MAX_LOYALTY_MULTIPLIER: u128 = 30_000;
+
+/// Loyalty tier of an account in a pool
+#[near(serializers = [json])]
+#[derive(Debug, PartialEq)]
+pub enum LoyaltyTier {
+    None,
+    Bronze,
+    Silver,
+    Gold,
+}
+
+/// Duration and multiplier of a loyalty tier
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct TierThreshold {
+    min_duration: u64,    // Seconds held at or above min_amount
+    multiplier_bps: u128, // Reward multiplier in basis points, 10000 is 1x
+}
+
+/// Loyalty settings of a pool
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct LoyaltyConfig {
+    min_amount: U128,          // Principal an account must hold for its time to count
+    grace_period: u64,         // Seconds below min_amount before the history is lost
+    tiers: Vec<TierThreshold>, // Bronze, Silver and Gold, in this order
+}
+
+/// Qualifying history of an account, kept after a full unstake
+#[near(serializers = [borsh])]
+pub struct LoyaltyRecord {
+    since: u64,             // Start of the qualifying time, moved forward by lapses
+    lapsed_at: Option<u64>, // Time the position fell below min_amount
+}
+
+#[near]
+impl StakingContract {
+    /// Set the loyalty tiers of a pool (only callable by the owner).
+    /// - `config`: Tiers in ascending order, `None` disables loyalty multipliers.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn set_loyalty_config(&mut self, config: Option<LoyaltyConfig>, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set loyalty config."
+        );
+        if let Some(config) = &config {
+            require!(config.tiers.len() <= 3, "At most 3 loyalty tiers");
+            let mut previous = TierThreshold {
+                min_duration: 0,
+                multiplier_bps: AAR_BASE,
+            };
+            for tier in config.tiers.iter() {
+                require!(
+                    tier.min_duration > previous.min_duration
+                        && tier.multiplier_bps >= previous.multiplier_bps,
+                    "Loyalty tiers should be ascending"
+                );
+                previous = tier.clone();
+            }
+            require!(
+                previous.multiplier_bps <= MAX_LOYALTY_MULTIPLIER,
+                "Cannot exceed MAX_LOYALTY_MULTIPLIER"
+            );
+        }
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.loyalty = config;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!("Loyalty config of pool {} updated", pool_id));
+    }
+
+    /// Query the loyalty settings of a pool
+    pub fn get_loyalty_config(&self, pool_id: Option<PoolId>) -> Option<LoyaltyConfig> {
+        self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID)).loyalty
+    }
+
+    /// Query the loyalty tier of an account in a pool
+    pub fn get_loyalty_tier(&self, account_id: AccountId, pool_id: Option<PoolId>) -> LoyaltyTier {
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let pool = self.load_pool(pool_id);
+        match self.loyalty_tier_index(pool_id, &pool, &account_id) {
+            Some(0) => LoyaltyTier::Bronze,
+            Some(1) => LoyaltyTier::Silver,
+            Some(_) => LoyaltyTier::Gold,
+            None => LoyaltyTier::None,
+        }
+    }
+}
+
+impl StakingContract {
+    /// Extra reward on top of the pending reward of a position from the loyalty tiers of
+    /// the account. The period since the last settlement is split where the account
+    /// reaches each tier, so every part earns the multiplier of the tier held during it.
+    pub(crate) fn loyalty_bonus(
+        &self,
+        pool_id: PoolId,
+        pool: &Pool,
+        account_id: &AccountId,
+        stake_info: &StakeInfo,
+    ) -> u128 {
+        let Some(since) = self.loyalty_since(pool_id, pool, account_id) else {
+            return 0;
+        };
+        let config = pool.loyalty.as_ref().unwrap();
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let end_time = pool.reward_end_time(current_time);
+        let start_time = std::cmp::min(stake_info.start_time, end_time);
+        let mut bonus = 0;
+        for (index, tier) in config.tiers.iter().enumerate() {
+            let tier_start = std::cmp::max(start_time, since.saturating_add(tier.min_duration));
+            let tier_end = config.tiers.get(index + 1).map_or(end_time, |next| {
+                std::cmp::min(end_time, since.saturating_add(next.min_duration))
+            });
+            if tier_end <= tier_start {
+                continue;
+            }
+            let reward = pool.calculate_reward(stake_info.amount, tier_end, tier_start);
+            bonus += (U256::from(reward) * U256::from(tier.multiplier_bps - AAR_BASE)
+                / U256::from(AAR_BASE))
+            .as_u128();
+        }
+        bonus
+    }
+
+    /// Record the principal of an account after it changed. Falling below the minimum
+    /// starts the grace period, coming back within it keeps the history.
+    pub(crate) fn update_loyalty(
+        &mut self,
+        pool_id: PoolId,
+        pool: &Pool,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        let Some(config) = &pool.loyalty else {
+            return;
+        };
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let key = (pool_id, account_id.clone());
+        let record = self.loyalty_records.get(&key);
+        let record = if amount >= config.min_amount.0 {
+            match record {
+                Some(LoyaltyRecord {
+                    since,
+                    lapsed_at: None,
+                }) => LoyaltyRecord {
+                    since,
+                    lapsed_at: None,
+                },
+                // Time spent below the minimum does not count
+                Some(LoyaltyRecord {
+                    since,
+                    lapsed_at: Some(lapsed_at),
+                }) if current_time <= lapsed_at + config.grace_period => LoyaltyRecord {
+                    since: since + (current_time - lapsed_at),
+                    lapsed_at: None,
+                },
+                Some(_) => LoyaltyRecord {
+                    since: current_time,
+                    lapsed_at: None,
+                },
+                // Positions opened before loyalty was configured count from their first stake
+                None => LoyaltyRecord {
+                    since: pool
+                        .stakes
+                        .get(account_id)
+                        .map_or(current_time, |stake_info| {
+                            std::cmp::min(stake_info.first_stake_time, current_time)
+                        }),
+                    lapsed_at: None,
+                },
+            }
+        } else {
+            match record {
+                Some(LoyaltyRecord {
+                    since,
+                    lapsed_at: None,
+                }) => LoyaltyRecord {
+                    since,
+                    lapsed_at: Some(current_time),
+                },
+                Some(record) => record,
+                None => return,
+            }
+        };
+        self.loyalty_records.insert(&key, &record);
+    }
+
+    /// Index of the highest tier reached by an account, `None` below Bronze.
+    fn loyalty_tier_index(
+        &self,
+        pool_id: PoolId,
+        pool: &Pool,
+        account_id: &AccountId,
+    ) -> Option<usize> {
+        let config = pool.loyalty.as_ref()?;
+        let since = self.loyalty_since(pool_id, pool, account_id)?;
+        let held = (env::block_timestamp() / NANOSECONDS).saturating_sub(since);
+        config
+            .tiers
+            .iter()
+            .rposition(|tier| held >= tier.min_duration)
+    }
+
+    /// Start of the qualifying time of an account, `None` while it is not qualifying.
+    fn loyalty_since(&self, pool_id: PoolId, pool: &Pool, account_id: &AccountId) -> Option<u64> {
+        let config = pool.loyalty.as_ref()?;
+        let since = match self.loyalty_records.get(&(pool_id, account_id.clone())) {
+            Some(LoyaltyRecord {
+                since,
+                lapsed_at: None,
+            }) => since,
+            Some(_) => return None,
+            // Positions opened before loyalty was configured count from their first stake
+            None => {
+                pool.stakes
+                    .get(account_id)
+                    .filter(|stake_info| stake_info.amount >= config.min_amount.0)?
+                    .first_stake_time
+            }
+        };
+        Some(since)
+    }
+}
@@ -0,0 +1,359 @@
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, Gas, NearToken, Promise, PromiseOrValue,
+};
+
+use crate::pool::DEFAULT_POOL_ID;
+use crate::{StakingContract, StakingContractExt, AAR_BASE, NANOSECONDS, U256};
+
+/// Maximum NFT multiplier (2x)
+pub const MAX_NFT_MULTIPLIER: u128 = 20_000;
+/// NEAR attached to `verify_nft` to cover the storage of a verified boost (0.01 NEAR).
+pub const NFT_BOOST_STORAGE_DEPOSIT: u128 = 10_000_000_000_000_000_000_000;
+
+/// NFT backing the reward boost of an account
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct NftBoost {
+    collection_id: AccountId, // NEP-171 collection
+    token_id: TokenId,
+    deposited: bool, // Held by the contract, otherwise verified with nft_token
+}
+
+#[near]
+impl StakingContract {
+    /// Register a NEP-171 collection whose holders get a reward boost (only callable by the owner).
+    /// - `multiplier_bps`: Reward multiplier in basis points, 10000 is 1x.
+    #[payable]
+    pub fn add_boost_collection(&mut self, collection_id: AccountId, multiplier_bps: u128) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can add boost collection."
+        );
+        require!(
+            (AAR_BASE..=MAX_NFT_MULTIPLIER).contains(&multiplier_bps),
+            "Multiplier should be between AAR_BASE and MAX_NFT_MULTIPLIER"
+        );
+        self.boost_collections
+            .insert(&collection_id, &multiplier_bps);
+        env::log_str(&format!(
+            "Boost collection {} added with multiplier {}",
+            collection_id, multiplier_bps
+        ));
+    }
+
+    /// Remove a boost collection (only callable by the owner).
+    /// Boosts from the collection stop applying, deposited NFTs can still be withdrawn.
+    #[payable]
+    pub fn remove_boost_collection(&mut self, collection_id: AccountId) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can remove boost collection."
+        );
+        self.boost_collections.remove(&collection_id);
+        env::log_str(&format!("Boost collection {} removed", collection_id));
+    }
+
+    /// Prove ownership of an NFT with `nft_token` to get the boost of its collection.
+    /// The boost is dropped by `refresh_nft_boost` once the NFT changes owner, or when the
+    /// NFT is verified or deposited by another account.
+    /// Requires NFT_BOOST_STORAGE_DEPOSIT, the rest of the deposit is refunded, all of it
+    /// if the NFT is not owned by the caller. The storage deposit is refunded once the
+    /// account has no boost left.
+    #[payable]
+    pub fn verify_nft(&mut self, collection_id: AccountId, token_id: TokenId) -> Promise {
+        let deposit = env::attached_deposit().as_yoctonear();
+        require!(
+            deposit >= NFT_BOOST_STORAGE_DEPOSIT,
+            "Attach NFT_BOOST_STORAGE_DEPOSIT to cover storage"
+        );
+        require!(
+            self.boost_collections.get(&collection_id).is_some(),
+            "Collection is not registered"
+        );
+        let account_id = env::predecessor_account_id();
+        require!(
+            !self
+                .nft_boosts
+                .get(&account_id)
+                .is_some_and(|boost| boost.deposited),
+            "Withdraw the deposited NFT first"
+        );
+        require!(
+            self.nft_holders
+                .get(&(collection_id.clone(), token_id.clone()))
+                .as_ref()
+                != Some(&account_id),
+            "NFT already verified"
+        );
+        nft_token(&collection_id, &token_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas::from_tgas(30))
+                .on_nft_verified(account_id, collection_id, token_id, deposit),
+        )
+    }
+
+    /// Callback: After nft_token, set the boost if the account owns the NFT and refund
+    /// the deposit not kept for storage.
+    #[private]
+    pub fn on_nft_verified(
+        &mut self,
+        account_id: AccountId,
+        collection_id: AccountId,
+        token_id: TokenId,
+        deposit: u128,
+        #[callback_result] call_result: Result<Option<Token>, near_sdk::PromiseError>,
+    ) -> bool {
+        let owned = matches!(&call_result, Ok(Some(token)) if token.owner_id == account_id);
+        if owned {
+            // The NFT has moved to this account, its previous holder loses the boost
+            self.release_nft_holder(&collection_id, &token_id);
+            self.set_nft_boost(
+                &account_id,
+                Some(NftBoost {
+                    collection_id,
+                    token_id,
+                    deposited: false,
+                }),
+            );
+        }
+        // A boost replacing a verified one reuses the storage deposit already held
+        let kept = if owned && self.nft_storage_deposits.get(&account_id).is_none() {
+            self.nft_storage_deposits
+                .insert(&account_id, &NFT_BOOST_STORAGE_DEPOSIT);
+            NFT_BOOST_STORAGE_DEPOSIT
+        } else {
+            0
+        };
+        if deposit > kept {
+            Promise::new(account_id).transfer(NearToken::from_yoctonear(deposit - kept));
+        }
+        owned
+    }
+
+    /// Check that the NFT behind a verified boost is still owned by the account, anyone can call.
+    pub fn refresh_nft_boost(&mut self, account_id: AccountId) -> Promise {
+        let boost = self
+            .nft_boosts
+            .get(&account_id)
+            .filter(|boost| !boost.deposited)
+            .expect("No verified NFT boost");
+        nft_token(&boost.collection_id, &boost.token_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas::from_tgas(30))
+                .on_nft_refreshed(account_id),
+        )
+    }
+
+    /// Callback: After nft_token, drop the boost if the NFT changed owner.
+    #[private]
+    pub fn on_nft_refreshed(
+        &mut self,
+        account_id: AccountId,
+        #[callback_result] call_result: Result<Option<Token>, near_sdk::PromiseError>,
+    ) -> bool {
+        let moved = match &call_result {
+            Ok(Some(token)) => token.owner_id != account_id,
+            Ok(None) => true,
+            // A failed query keeps the boost
+            Err(_) => false,
+        };
+        if moved {
+            self.set_nft_boost(&account_id, None);
+        }
+        !moved
+    }
+
+    /// Withdraw the NFT deposited for the boost
+    #[payable]
+    pub fn withdraw_nft(&mut self) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let boost = self
+            .nft_boosts
+            .get(&account_id)
+            .filter(|boost| boost.deposited)
+            .expect("No deposited NFT");
+        self.set_nft_boost(&account_id, None);
+        Promise::new(boost.collection_id.clone())
+            .function_call(
+                "nft_transfer".to_string(),
+                serde_json::json!({
+                    "receiver_id": account_id,
+                    "token_id": boost.token_id,
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_tgas(20),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(30))
+                    .on_nft_withdrawn(account_id, boost),
+            )
+    }
+
+    /// Callback: After nft_transfer, keep the NFT deposited if the transfer failed.
+    #[private]
+    pub fn on_nft_withdrawn(
+        &mut self,
+        account_id: AccountId,
+        boost: NftBoost,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                self.set_nft_boost(&account_id, Some(boost));
+                false
+            }
+        }
+    }
+
+    /// Query boost collections and their multipliers
+    pub fn get_boost_collections(&self) -> Vec<(AccountId, u128)> {
+        self.boost_collections.to_vec()
+    }
+
+    /// Query the NFT boost of an account
+    pub fn get_nft_boost(&self, account_id: AccountId) -> Option<NftBoost> {
+        self.nft_boosts.get(&account_id)
+    }
+}
+
+/// Implementation of NEP-171 `nft_on_transfer` method
+#[near]
+impl NonFungibleTokenReceiver for StakingContract {
+    /// Deposit an NFT of a boost collection, the previous owner gets the boost.
+    /// Returns true to send the NFT back.
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let _ = (sender_id, msg);
+        let collection_id = env::predecessor_account_id();
+        let rejected = if self.boost_collections.get(&collection_id).is_none() {
+            Some("collection is not registered")
+        } else if self
+            .nft_boosts
+            .get(&previous_owner_id)
+            .is_some_and(|boost| boost.deposited)
+        {
+            Some("an NFT is already deposited")
+        } else {
+            None
+        };
+        if let Some(reason) = rejected {
+            env::log_str(&format!(
+                "Return {} of {} to {}: {}",
+                token_id, collection_id, previous_owner_id, reason
+            ));
+            return PromiseOrValue::Value(true);
+        }
+        // An account that verified the NFT before it was deposited loses the boost
+        self.release_nft_holder(&collection_id, &token_id);
+        self.set_nft_boost(
+            &previous_owner_id,
+            Some(NftBoost {
+                collection_id,
+                token_id,
+                deposited: true,
+            }),
+        );
+        PromiseOrValue::Value(false)
+    }
+}
+
+impl StakingContract {
+    /// Extra reward on top of `reward` from the NFT boost of the account.
+    pub(crate) fn nft_bonus(&self, account_id: &AccountId, reward: u128) -> u128 {
+        let Some(multiplier) = self
+            .nft_boosts
+            .get(account_id)
+            .and_then(|boost| self.boost_collections.get(&boost.collection_id))
+        else {
+            return 0;
+        };
+        (U256::from(reward) * U256::from(multiplier - AAR_BASE) / U256::from(AAR_BASE)).as_u128()
+    }
+
+    /// Drop the boost of the account recorded as the holder of an NFT, if any.
+    fn release_nft_holder(&mut self, collection_id: &AccountId, token_id: &TokenId) {
+        if let Some(holder_id) = self
+            .nft_holders
+            .get(&(collection_id.clone(), token_id.clone()))
+        {
+            self.set_nft_boost(&holder_id, None);
+        }
+    }
+
+    /// Replace the NFT boost of an account and the holder recorded for its NFT. Positions
+    /// of the account are settled first so the previous multiplier applies up to now.
+    /// Removing the boost refunds the storage deposit paid by `verify_nft`.
+    fn set_nft_boost(&mut self, account_id: &AccountId, boost: Option<NftBoost>) {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        // Only the pools with a position are visited, so the cost does not grow with the
+        // pool count. Default pool positions from before migrate are not tracked.
+        let mut pool_ids = self.account_pools.get(account_id).unwrap_or_default();
+        if !pool_ids.contains(&DEFAULT_POOL_ID) {
+            pool_ids.push(DEFAULT_POOL_ID);
+        }
+        for pool_id in pool_ids {
+            let mut pool = self.load_pool(pool_id);
+            let Some(mut stake_info) = pool.stakes.get(account_id) else {
+                continue;
+            };
+            self.checkpoint_account(pool_id, &mut pool, account_id, stake_info.amount);
+            stake_info.accumulated_reward +=
+                self.settle_boosted_reward(pool_id, &mut pool, account_id, &stake_info);
+            stake_info.start_time = current_time;
+            pool.stakes.insert(account_id, &stake_info);
+            self.save_pool(pool_id, &pool);
+        }
+        if let Some(previous) = self.nft_boosts.get(account_id) {
+            self.nft_holders
+                .remove(&(previous.collection_id, previous.token_id));
+        }
+        match &boost {
+            Some(boost) => {
+                self.nft_boosts.insert(account_id, boost);
+                self.nft_holders.insert(
+                    &(boost.collection_id.clone(), boost.token_id.clone()),
+                    account_id,
+                );
+                env::log_str(&format!(
+                    "NFT boost of {} set to {} of {}",
+                    account_id, boost.token_id, boost.collection_id
+                ));
+            }
+            None => {
+                self.nft_boosts.remove(account_id);
+                if let Some(deposit) = self.nft_storage_deposits.remove(account_id) {
+                    Promise::new(account_id.clone()).transfer(NearToken::from_yoctonear(deposit));
+                }
+                env::log_str(&format!("NFT boost of {} removed", account_id));
+            }
+        }
+    }
+}
+
+/// Query a token of a NEP-171 collection.
+fn nft_token(collection_id: &AccountId, token_id: &TokenId) -> Promise {
+    Promise::new(collection_id.clone()).function_call(
+        "nft_token".to_string(),
+        serde_json::json!({ "token_id": token_id })
+            .to_string()
+            .into_bytes(),
+        NearToken::from_near(0),
+        Gas::from_tgas(5),
+    )
+}
@@ -0,0 +1,113 @@
+use near_sdk::{assert_one_yocto, env, near, AccountId, Gas, NearToken, Promise};
+
+use crate::pool::{PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt};
+
+#[near]
+impl StakingContract {
+    /// Withdraw the payout recorded after a failed transfer.
+    /// - `receiver_id`: Account receiving the tokens, defaults to the caller.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn withdraw_pending(
+        &mut self,
+        receiver_id: Option<AccountId>,
+        pool_id: Option<PoolId>,
+    ) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let amount = self
+            .pending_payouts
+            .remove(&(pool_id, account_id.clone()))
+            .expect("No pending payout for this account");
+        let mut pool = self.load_pool(pool_id);
+        pool.total_pending_payout -= amount;
+        self.save_pool(pool_id, &pool);
+        let receiver_id = receiver_id.unwrap_or_else(|| account_id.clone());
+        env::log_str(&format!(
+            "Withdraw pending payout of {} from {} to {}",
+            amount, account_id, receiver_id
+        ));
+
+        Promise::new(pool.staked_token)
+            .function_call(
+                "ft_transfer".to_string(),
+                serde_json::json!({
+                    "receiver_id": receiver_id,
+                    "amount": amount.to_string(),
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_gas(20_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                    .on_withdraw_pending(pool_id, account_id, amount),
+            )
+    }
+
+    /// Callback: After ft_transfer, credit the payout back if the transfer failed.
+    #[private]
+    pub fn on_withdraw_pending(
+        &mut self,
+        pool_id: PoolId,
+        account_id: AccountId,
+        amount: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                self.credit_pending_payout(pool_id, &account_id, amount);
+                false
+            }
+        }
+    }
+
+    /// Query the pending payout of an account
+    pub fn get_pending_payout(&self, account_id: AccountId, pool_id: Option<PoolId>) -> u128 {
+        self.pending_payouts
+            .get(&(pool_id.unwrap_or(DEFAULT_POOL_ID), account_id))
+            .unwrap_or(0)
+    }
+
+    /// Query total pending payout
+    pub fn get_total_pending_payout(&self, pool_id: Option<PoolId>) -> u128 {
+        self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID))
+            .total_pending_payout
+    }
+}
+
+impl StakingContract {
+    /// Record a payout that could not be transferred so the account can withdraw it later.
+    pub(crate) fn credit_pending_payout(
+        &mut self,
+        pool_id: PoolId,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        self.add_pending_payout(pool_id, account_id, amount);
+        env::log_str(&format!(
+            "Payout of {} to {} failed, recorded as pending",
+            amount, account_id
+        ));
+    }
+
+    /// Add to the pending payout of an account, withdrawn with `withdraw_pending`.
+    pub(crate) fn add_pending_payout(
+        &mut self,
+        pool_id: PoolId,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        let key = (pool_id, account_id.clone());
+        let pending = self.pending_payouts.get(&key).unwrap_or(0);
+        self.pending_payouts.insert(&key, &(pending + amount));
+        let mut pool = self.load_pool(pool_id);
+        pool.total_pending_payout += amount;
+        self.save_pool(pool_id, &pool);
+    }
+}
@@ -0,0 +1,464 @@
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::events::RefundReason;
+use crate::loyalty::LoyaltyConfig;
+use crate::referral::ReferralFunding;
+use crate::reward_tokens::RewardToken;
+use crate::seeding::SeedRecord;
+use crate::vesting::VestingConfig;
+use crate::{
+    PauseStatus, StakeInfo, StakingContract, StakingContractExt, AAR_BASE, MAX_LOCK_DURATION,
+    MAX_TOTAL_REWARD, NANOSECONDS, SECONDS_IN_A_YEAR, WEEK,
+};
+
+/// Pool identifier, pools are numbered in creation order
+pub type PoolId = u32;
+/// Pool created by `new`, selected when no pool is given
+pub const DEFAULT_POOL_ID: PoolId = 0;
+/// Maximum number of weeks with an early AAR
+pub const MAX_EARLY_AAR_WEEKS: usize = 52;
+/// Maximum AAR of a pool (1000%)
+pub const MAX_AAR: u128 = 100_000;
+
+/// Staking pool with its own token, reward schedule, lock settings, pause switches and totals
+#[near(serializers = [borsh])]
+pub struct Pool {
+    pub(crate) staked_token: AccountId, // NEP-141 token staked in this pool
+    pub(crate) stakes: UnorderedMap<AccountId, StakeInfo>, // User staking information
+    pub(crate) stake_start_time: u64,   // Start time of stake
+    pub(crate) stake_end_time: u64,     // No rewards after this time, 0 means no end time
+    pub(crate) lock_duration: u64,      // Lock duration
+    pub(crate) early_aar: Vec<u128>,    // AAR of each week after the start time
+    pub(crate) aar: u128,               // AAR once the early weeks are over
+    pub(crate) pause_status: PauseStatus, // Pause switches
+    pub(crate) total_staked: u128,      // Total amount staked
+    pub(crate) total_claimed_reward: u128, // Total amount of claimed reward
+    pub(crate) total_reward: u128,      // Total amount of reward
+    pub(crate) accrued_reward_liability: u128, // Rewards accrued by all stakers and not yet paid
+    pub(crate) liability_updated_at: u64, // Time the accrued reward liability was updated
+    pub(crate) reward_tokens: Vec<RewardToken>, // Partner tokens paid as rewards
+    pub(crate) total_pending_payout: u128, // Total amount of pending payouts
+    pub(crate) previous_season: Option<PoolId>, // Season this pool follows
+    pub(crate) next_season: Option<PoolId>, // Season positions roll into
+    pub(crate) max_stake_per_account: Option<u128>, // Maximum principal of one account
+    pub(crate) max_total_staked: Option<u128>, // Maximum total_staked
+    pub(crate) min_stake: u128,         // Minimum principal of a position
+    pub(crate) total_settled_reward: u128, // Rewards settled into positions, paid or forfeited
+    pub(crate) reward_remainder: u128, // Rounding remainders of settled rewards, see `settle_reward`
+    pub(crate) referral_bps: u128,     // Share of paid rewards credited to referrers
+    pub(crate) referral_funding: ReferralFunding, // Source of referral rewards
+    pub(crate) referral_budget: u128,  // Unspent referral budget
+    pub(crate) total_referral_reward: u128, // Referral rewards credited so far
+    pub(crate) loyalty: Option<LoyaltyConfig>, // Loyalty tiers
+    pub(crate) total_bonus_reward: u128, // Loyalty and NFT bonuses settled so far
+    pub(crate) vesting: Option<VestingConfig>, // Vesting of paid rewards
+    pub(crate) total_vesting: u128,    // Rewards vesting and not yet withdrawn
+    pub(crate) airdrop_reserve: u128,  // Airdropped bonuses not yet claimed
+    pub(crate) finalized: bool,        // Closed out, only rewards already owed are paid
+    pub(crate) swept_reward: u128,     // Unused reward swept when finalizing
+}
+
+/// Pool settings and totals
+#[near(serializers = [json])]
+pub struct PoolView {
+    staked_token: AccountId,
+    stake_start_time: u64,
+    stake_end_time: u64,
+    lock_duration: u64,
+    early_aar: Vec<u128>,
+    aar: u128,
+    pause_status: PauseStatus,
+    total_staked: U128,
+    total_claimed_reward: U128,
+    total_reward: U128,
+    number_of_stakers: u64,
+    previous_season: Option<PoolId>,
+    next_season: Option<PoolId>,
+    max_stake_per_account: Option<U128>,
+    max_total_staked: Option<U128>,
+    min_stake: U128,
+    total_settled_reward: U128,
+    reward_remainder: U128,
+    finalized: bool,
+}
+
+/// `ft_on_transfer` msg, an empty msg stakes in the default pool
+#[near(serializers = [json])]
+pub struct TransferMessage {
+    pub(crate) pool_id: Option<PoolId>,
+    pub(crate) referrer: Option<AccountId>, // Referrer of the sender, only the first one is kept
+    #[serde(default)]
+    pub(crate) referral_budget: bool, // Fund the referral budget instead of staking
+    pub(crate) seed: Option<Vec<SeedRecord>>, // Positions migrated by the owner, see `seed_stakes`
+}
+
+#[near]
+impl StakingContract {
+    /// Create a pool (only callable by the owner).
+    /// - `staked_token`: NEP-141 token staked in the pool, also used to pay its rewards.
+    /// - `total_reward`: Total reward.
+    /// - `lock_duration`: Lock duration.
+    /// - `early_aar`: AAR of each week after the pool is created.
+    /// - `aar`: AAR once the early weeks are over.
+    #[payable]
+    pub fn create_pool(
+        &mut self,
+        staked_token: AccountId,
+        total_reward: U128,
+        lock_duration: u64,
+        early_aar: Vec<u128>,
+        aar: u128,
+    ) -> PoolId {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can create pool."
+        );
+        assert_valid_pool_settings(total_reward.0, lock_duration, &early_aar, aar);
+        let pool_id = self.pools.len() as PoolId;
+        let pool = Pool::new(
+            pool_id,
+            staked_token.clone(),
+            total_reward.0,
+            lock_duration,
+            early_aar,
+            aar,
+        );
+        self.pools.insert(&pool_id, &pool);
+        env::log_str(&format!("Pool {} created for {}", pool_id, staked_token));
+        pool_id
+    }
+
+    /// Set the stake caps of a pool (only callable by the owner).
+    /// Transfers above a cap are partially refunded, existing stakes are kept.
+    /// - `max_stake_per_account`: Maximum principal of one account, `None` removes it.
+    /// - `max_total_staked`: Maximum total stake of the pool, `None` removes it.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn set_stake_caps(
+        &mut self,
+        max_stake_per_account: Option<U128>,
+        max_total_staked: Option<U128>,
+        pool_id: Option<PoolId>,
+    ) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set stake caps."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.max_stake_per_account = max_stake_per_account.map(|cap| cap.0);
+        pool.max_total_staked = max_total_staked.map(|cap| cap.0);
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Stake caps of pool {} updated to {:?} per account and {:?} in total",
+            pool_id, pool.max_stake_per_account, pool.max_total_staked
+        ));
+    }
+
+    /// Set the minimum principal of a position (only callable by the owner).
+    /// Smaller transfers are refunded and partial unstakes cannot leave less, existing
+    /// positions below it are kept.
+    /// - `min_stake`: Minimum principal, 0 disables it.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn set_min_stake(&mut self, min_stake: U128, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set min stake."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.min_stake = min_stake.0;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Min stake of pool {} updated to {}",
+            pool_id, pool.min_stake
+        ));
+    }
+
+    /// Query a pool
+    pub fn get_pool(&self, pool_id: PoolId) -> Option<PoolView> {
+        self.pools.get(&pool_id).map(|pool| pool.view())
+    }
+
+    /// Query pools in creation order
+    pub fn get_pools(&self, offset: Option<u64>, limit: Option<u64>) -> Vec<(PoolId, PoolView)> {
+        let start = offset.unwrap_or(0);
+        let l = limit.unwrap_or(50);
+        self.pools
+            .iter()
+            .skip(start as usize)
+            .take(l as usize)
+            .map(|(pool_id, pool)| (pool_id, pool.view()))
+            .collect()
+    }
+}
+
+impl StakingContract {
+    pub(crate) fn load_pool(&self, pool_id: PoolId) -> Pool {
+        self.pools
+            .get(&pool_id)
+            .unwrap_or_else(|| env::panic_str("Pool not found"))
+    }
+
+    /// Write a pool back, required after any change including its stakes map.
+    pub(crate) fn save_pool(&mut self, pool_id: PoolId, pool: &Pool) {
+        self.pools.insert(&pool_id, pool);
+    }
+}
+
+impl Pool {
+    pub(crate) fn new(
+        pool_id: PoolId,
+        staked_token: AccountId,
+        total_reward: u128,
+        lock_duration: u64,
+        early_aar: Vec<u128>,
+        aar: u128,
+    ) -> Self {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        Self {
+            staked_token,
+            stakes: UnorderedMap::new(stakes_prefix(pool_id)),
+            stake_start_time: current_time,
+            stake_end_time: 0,
+            lock_duration,
+            early_aar,
+            aar,
+            pause_status: PauseStatus::default(),
+            total_staked: 0,
+            total_claimed_reward: 0,
+            total_reward,
+            accrued_reward_liability: 0,
+            liability_updated_at: current_time,
+            reward_tokens: Vec::new(),
+            total_pending_payout: 0,
+            previous_season: None,
+            next_season: None,
+            max_stake_per_account: None,
+            max_total_staked: None,
+            min_stake: 0,
+            total_settled_reward: 0,
+            reward_remainder: 0,
+            referral_bps: 0,
+            referral_funding: ReferralFunding::TotalReward,
+            referral_budget: 0,
+            total_referral_reward: 0,
+            loyalty: None,
+            total_bonus_reward: 0,
+            vesting: None,
+            total_vesting: 0,
+            airdrop_reserve: 0,
+            finalized: false,
+            swept_reward: 0,
+        }
+    }
+
+    /// End of the reward window at `current_time`, capped by the stake end time.
+    pub(crate) fn reward_end_time(&self, current_time: u64) -> u64 {
+        if self.stake_end_time == 0 {
+            current_time
+        } else {
+            std::cmp::min(current_time, self.stake_end_time)
+        }
+    }
+
+    /// Amount an account holding `staked` can still add under the stake caps.
+    pub(crate) fn stake_room(&self, staked: u128) -> u128 {
+        let account_room = self
+            .max_stake_per_account
+            .map_or(u128::MAX, |cap| cap.saturating_sub(staked));
+        let total_room = self
+            .max_total_staked
+            .map_or(u128::MAX, |cap| cap.saturating_sub(self.total_staked));
+        std::cmp::min(account_room, total_room)
+    }
+
+    /// Part of `amount` a position of `staked` can add within the stake caps, with the reason
+    /// the rest is refused. Nothing is admitted if the position would be below `min_stake`.
+    pub(crate) fn admitted_stake(&self, staked: u128, amount: u128) -> (u128, RefundReason) {
+        let accepted = std::cmp::min(amount, self.stake_room(staked));
+        if staked + accepted < self.min_stake {
+            return (0, RefundReason::BelowMinStake);
+        }
+        (accepted, RefundReason::StakeCapReached)
+    }
+
+    /// Whether the stake end time has passed, positions are no longer locked after it.
+    pub(crate) fn has_ended(&self, current_time: u64) -> bool {
+        self.stake_end_time != 0 && current_time >= self.stake_end_time
+    }
+
+    /// Reward earned by a position since its start time, not yet in `accumulated_reward`.
+    pub(crate) fn pending_reward(&self, stake_info: &StakeInfo) -> u128 {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let reward_end_time = self.reward_end_time(current_time);
+        let start_time = std::cmp::min(stake_info.start_time, reward_end_time);
+        self.calculate_reward(stake_info.amount, reward_end_time, start_time)
+    }
+
+    /// Settle the pending reward of a position, recording it in `total_settled_reward`.
+    /// The fraction cut off by the division is added to `reward_remainder`, in units of
+    /// 1 / (SECONDS_IN_A_YEAR * AAR_BASE), so that `total_settled_reward` plus
+    /// `reward_remainder / (SECONDS_IN_A_YEAR * AAR_BASE)` is the exact accrued total.
+    pub(crate) fn settle_reward(&mut self, stake_info: &StakeInfo) -> u128 {
+        let scaled = self.pending_scaled_reward(stake_info);
+        let reward = scaled / (SECONDS_IN_A_YEAR * AAR_BASE);
+        self.total_settled_reward += reward;
+        self.reward_remainder += scaled % (SECONDS_IN_A_YEAR * AAR_BASE);
+        reward
+    }
+
+    fn pending_scaled_reward(&self, stake_info: &StakeInfo) -> u128 {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let reward_end_time = self.reward_end_time(current_time);
+        let start_time = std::cmp::min(stake_info.start_time, reward_end_time);
+        self.scaled_reward(stake_info.amount, reward_end_time, start_time)
+    }
+
+    /// Calculate rewards based on staking amount and duration
+    pub(crate) fn calculate_reward(
+        &self,
+        amount: u128,
+        current_time: u64,
+        start_time: u64,
+    ) -> u128 {
+        self.scaled_reward(amount, current_time, start_time) / (SECONDS_IN_A_YEAR * AAR_BASE)
+    }
+
+    /// Reward multiplied by SECONDS_IN_A_YEAR * AAR_BASE, before rounding down.
+    fn scaled_reward(&self, amount: u128, current_time: u64, start_time: u64) -> u128 {
+        self.checked_scaled_reward(amount, current_time, start_time)
+            .unwrap_or_else(|| env::panic_str("Reward overflow"))
+    }
+
+    /// `scaled_reward`, `None` if it overflows.
+    pub(crate) fn checked_scaled_reward(
+        &self,
+        amount: u128,
+        current_time: u64,
+        start_time: u64,
+    ) -> Option<u128> {
+        let mut reward = 0u128;
+        // Reward formula: Principal * AAR * duration / (SECONDS_IN_A_YEAR * 10000)
+        for (index, aar) in self.early_aar.iter().enumerate() {
+            let aar_start_at = self.stake_start_time + (index as u64 * WEEK);
+            let aar_end_at = self.stake_start_time + ((index + 1) as u64 * WEEK);
+            // Skip if the entire interval is outside the range
+            if current_time < aar_start_at || start_time >= aar_end_at {
+                continue;
+            }
+            #[allow(clippy::collapsible_else_if)]
+            let reward_duration = if start_time >= aar_start_at {
+                if current_time <= aar_end_at {
+                    current_time - start_time
+                } else {
+                    aar_end_at - start_time
+                }
+            } else {
+                if current_time <= aar_end_at {
+                    current_time - aar_start_at
+                } else {
+                    aar_end_at - aar_start_at
+                }
+            };
+            reward = amount
+                .checked_mul(*aar)?
+                .checked_mul(reward_duration as u128)?
+                .checked_add(reward)?;
+        }
+        let last_interval_end = self.stake_start_time + (self.early_aar.len() as u64 * WEEK);
+        if current_time >= last_interval_end {
+            let reward_duration = if start_time >= last_interval_end {
+                current_time - start_time
+            } else {
+                current_time - last_interval_end
+            };
+            reward = amount
+                .checked_mul(self.aar)?
+                .checked_mul(reward_duration as u128)?
+                .checked_add(reward)?;
+        }
+        Some(reward)
+    }
+
+    fn view(&self) -> PoolView {
+        PoolView {
+            staked_token: self.staked_token.clone(),
+            stake_start_time: self.stake_start_time,
+            stake_end_time: self.stake_end_time,
+            lock_duration: self.lock_duration,
+            early_aar: self.early_aar.clone(),
+            aar: self.aar,
+            pause_status: self.pause_status.clone(),
+            total_staked: U128(self.total_staked),
+            total_claimed_reward: U128(self.total_claimed_reward),
+            total_reward: U128(self.total_reward),
+            number_of_stakers: self.stakes.len(),
+            previous_season: self.previous_season,
+            next_season: self.next_season,
+            max_stake_per_account: self.max_stake_per_account.map(U128),
+            max_total_staked: self.max_total_staked.map(U128),
+            min_stake: U128(self.min_stake),
+            total_settled_reward: U128(self.total_settled_reward),
+            reward_remainder: U128(self.reward_remainder),
+            finalized: self.finalized,
+        }
+    }
+}
+
+pub(crate) fn assert_valid_pool_settings(
+    total_reward: u128,
+    lock_duration: u64,
+    early_aar: &[u128],
+    aar: u128,
+) {
+    assert!(total_reward > 0, "Total reward should gt 0.");
+    assert!(
+        total_reward <= MAX_TOTAL_REWARD,
+        "Total reward should le MAX_TOTAL_REWARD"
+    );
+    require!(
+        lock_duration <= MAX_LOCK_DURATION,
+        "Cannot exceed MAX_LOCK_DURATION"
+    );
+    require!(
+        early_aar.len() <= MAX_EARLY_AAR_WEEKS,
+        "Cannot exceed MAX_EARLY_AAR_WEEKS"
+    );
+    require!(
+        aar <= MAX_AAR && early_aar.iter().all(|rate| *rate <= MAX_AAR),
+        "AAR should le MAX_AAR"
+    );
+}
+
+/// Storage prefix of the stakes of a pool. The default pool keeps the prefix of the
+/// single pool layout so existing stakes stay in place.
+fn stakes_prefix(pool_id: PoolId) -> Vec<u8> {
+    if pool_id == DEFAULT_POOL_ID {
+        b"s".to_vec()
+    } else {
+        format!("s{}", pool_id).into_bytes()
+    }
+}
+
+/// Parse an `ft_on_transfer` msg, `None` if the msg is invalid.
+pub(crate) fn parse_transfer_message(msg: &str) -> Option<TransferMessage> {
+    if msg.is_empty() {
+        return Some(TransferMessage {
+            pool_id: None,
+            referrer: None,
+            referral_budget: false,
+            seed: None,
+        });
+    }
+    serde_json::from_str(msg).ok()
+}
@@ -0,0 +1,161 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, AAR_BASE, U256};
+
+/// Maximum referral share (50%)
+pub const MAX_REFERRAL_BPS: u128 = 5_000;
+
+/// Where referral rewards are paid from
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReferralFunding {
+    TotalReward, // Counted against the pool's total_reward
+    Budget,      // Separate budget funded through ft_transfer_call
+}
+
+/// Referral settings of a pool
+#[near(serializers = [json])]
+pub struct ReferralConfig {
+    share_bps: u128,             // Share of the referee's rewards, in basis points
+    funding: ReferralFunding,    // Source of referral rewards
+    budget: U128,                // Unspent referral budget
+    total_referral_reward: U128, // Referral rewards paid so far
+}
+
+/// Referrals of an account
+#[near(serializers = [json])]
+pub struct ReferralStats {
+    referral_count: u64, // Accounts attributed to the referrer, in all pools
+    earned: U128,        // Referral rewards earned in the pool
+}
+
+#[near]
+impl StakingContract {
+    /// Set the referral share of a pool (only callable by the owner).
+    /// - `share_bps`: Share of the referee's paid rewards credited to the referrer, 0 disables it.
+    /// - `funding`: Pay from the pool's total reward or from its referral budget.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn set_referral_config(
+        &mut self,
+        share_bps: u128,
+        funding: ReferralFunding,
+        pool_id: Option<PoolId>,
+    ) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set referral config."
+        );
+        require!(
+            share_bps <= MAX_REFERRAL_BPS,
+            "Cannot exceed MAX_REFERRAL_BPS"
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.referral_bps = share_bps;
+        pool.referral_funding = funding;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Referral share of pool {} updated to {} from {:?}",
+            pool_id, pool.referral_bps, pool.referral_funding
+        ));
+    }
+
+    /// Query the referral settings of a pool
+    pub fn get_referral_config(&self, pool_id: Option<PoolId>) -> ReferralConfig {
+        let pool = self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID));
+        ReferralConfig {
+            share_bps: pool.referral_bps,
+            funding: pool.referral_funding,
+            budget: U128(pool.referral_budget),
+            total_referral_reward: U128(pool.total_referral_reward),
+        }
+    }
+
+    /// Query the referrer of an account
+    pub fn get_referrer(&self, account_id: AccountId) -> Option<AccountId> {
+        self.referrers.get(&account_id)
+    }
+
+    /// Query the referral count and the referral rewards earned in a pool by a referrer
+    pub fn get_referral_stats(
+        &self,
+        referrer_id: AccountId,
+        pool_id: Option<PoolId>,
+    ) -> ReferralStats {
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        ReferralStats {
+            referral_count: self.referral_counts.get(&referrer_id).unwrap_or(0),
+            earned: U128(
+                self.referral_earnings
+                    .get(&(pool_id, referrer_id))
+                    .unwrap_or(0),
+            ),
+        }
+    }
+}
+
+impl StakingContract {
+    /// Attribute an account to a referrer, the first referrer is kept.
+    pub(crate) fn set_referrer(&mut self, account_id: &AccountId, referrer_id: AccountId) {
+        if &referrer_id == account_id || self.referrers.get(account_id).is_some() {
+            return;
+        }
+        self.referrers.insert(account_id, &referrer_id);
+        let count = self.referral_counts.get(&referrer_id).unwrap_or(0);
+        self.referral_counts.insert(&referrer_id, &(count + 1));
+        env::log_str(&format!("{} referred by {}", account_id, referrer_id));
+    }
+
+    /// Credit the referrer of an account with its share of `reward_amount`.
+    /// The referral reward is added to the pending payouts of the referrer,
+    /// who withdraws it with `withdraw_pending`. The caller saves the pool.
+    pub(crate) fn reward_referrer(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        reward_amount: u128,
+    ) {
+        let Some(referrer_id) = self.referrers.get(account_id) else {
+            return;
+        };
+        // A finalized pool only pays the rewards it already owes
+        if pool.referral_bps == 0 || pool.finalized || self.denylist.contains(&referrer_id) {
+            return;
+        }
+        let share = (U256::from(reward_amount) * U256::from(pool.referral_bps)
+            / U256::from(AAR_BASE))
+        .as_u128();
+        let amount = match pool.referral_funding {
+            ReferralFunding::TotalReward => {
+                let amount = std::cmp::min(share, pool.remaining_reward_budget());
+                pool.total_claimed_reward += amount;
+                amount
+            }
+            ReferralFunding::Budget => {
+                let amount = std::cmp::min(share, pool.referral_budget);
+                pool.referral_budget -= amount;
+                amount
+            }
+        };
+        if amount == 0 {
+            return;
+        }
+        pool.total_referral_reward += amount;
+        pool.total_pending_payout += amount;
+        let key = (pool_id, referrer_id.clone());
+        let pending = self.pending_payouts.get(&key).unwrap_or(0);
+        self.pending_payouts.insert(&key, &(pending + amount));
+        let earned = self.referral_earnings.get(&key).unwrap_or(0);
+        self.referral_earnings.insert(&key, &(earned + amount));
+        env::log_str(&format!(
+            "Referral reward of {} to {} for {}",
+            amount, referrer_id, account_id
+        ));
+    }
+}
@@ -0,0 +1,443 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, Gas, NearToken, Promise, PromiseOrValue,
+};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, NANOSECONDS, U256};
+
+/// Maximum number of partner reward tokens
+pub const MAX_REWARD_TOKENS: usize = 5;
+/// Precision of the reward per staked token accumulator
+const REWARD_PER_SHARE_PRECISION: u128 = 1_000_000_000_000_000_000_000_000;
+
+/// Emission rate of a reward token over a time range
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct RatePeriod {
+    start_time: u64,         // Period start timestamp in seconds
+    end_time: u64,           // Period end timestamp in seconds
+    reward_per_second: U128, // Tokens emitted per second, shared by all stakers
+}
+
+/// Partner token paid as reward next to the staked token
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct RewardToken {
+    token_id: AccountId,       // NEP-141 reward token contract
+    schedule: Vec<RatePeriod>, // Emission schedule
+    budget: u128,              // Total amount funded through ft_on_transfer
+    distributed: u128,         // Amount emitted to stakers so far
+    claimed: u128,             // Amount claimed by stakers
+    reward_per_share: u128,    // Accumulated reward per staked token
+    last_update: u64,          // Time the accumulator was updated
+}
+
+/// Reward token accrual of an account
+#[near(serializers = [borsh])]
+#[derive(Default)]
+pub struct AccountReward {
+    reward_per_share_paid: u128, // Accumulator value at the last settlement
+    unclaimed: u128,             // Rewards accrued and not claimed
+}
+
+#[near]
+impl StakingContract {
+    /// Add a partner reward token to a pool (only callable by the owner).
+    /// - `token_id`: NEP-141 token contract, funded through `ft_transfer_call`.
+    /// - `schedule`: Emission schedule.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn add_reward_token(
+        &mut self,
+        token_id: AccountId,
+        schedule: Vec<RatePeriod>,
+        pool_id: Option<PoolId>,
+    ) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can add reward token."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(
+            token_id != pool.staked_token,
+            "Staked token rewards are set with set_total_reward"
+        );
+        require!(
+            pool.reward_token_index(&token_id).is_none(),
+            "Reward token already exists"
+        );
+        require!(
+            pool.reward_tokens.len() < MAX_REWARD_TOKENS,
+            "Cannot exceed MAX_REWARD_TOKENS"
+        );
+        assert_valid_schedule(&schedule);
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        pool.reward_tokens.push(RewardToken {
+            token_id: token_id.clone(),
+            schedule,
+            budget: 0,
+            distributed: 0,
+            claimed: 0,
+            reward_per_share: 0,
+            last_update: current_time,
+        });
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Reward token {} added to pool {}",
+            token_id, pool_id
+        ));
+    }
+
+    /// Replace the emission schedule of a reward token (only callable by the owner).
+    /// Rewards emitted so far are kept.
+    #[payable]
+    pub fn set_reward_schedule(
+        &mut self,
+        token_id: AccountId,
+        schedule: Vec<RatePeriod>,
+        pool_id: Option<PoolId>,
+    ) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set reward schedule."
+        );
+        assert_valid_schedule(&schedule);
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        let index = pool
+            .reward_token_index(&token_id)
+            .expect("Reward token not found");
+        pool.update_reward_tokens();
+        pool.reward_tokens[index].schedule = schedule;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Reward schedule of {} in pool {} updated",
+            token_id, pool_id
+        ));
+    }
+
+    /// Send the budget of a reward token that was never emitted back to the owner once its
+    /// schedule has ended (only callable by the owner). This covers the emission skipped
+    /// while nobody was staked and the budget left over after the last period.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn sweep_reward_token(
+        &mut self,
+        token_id: AccountId,
+        pool_id: Option<PoolId>,
+    ) -> PromiseOrValue<bool> {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can sweep reward token."
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        let index = pool
+            .reward_token_index(&token_id)
+            .expect("Reward token not found");
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        require!(
+            pool.reward_tokens[index]
+                .schedule
+                .iter()
+                .all(|period| period.end_time <= current_time),
+            "Reward schedule has not ended"
+        );
+        pool.update_reward_tokens();
+        let token = &mut pool.reward_tokens[index];
+        let unused = token.budget - token.distributed;
+        token.budget = token.distributed;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Sweep {} of {} from pool {} to {}",
+            unused, token_id, pool_id, self.owner_id
+        ));
+
+        if unused == 0 {
+            return PromiseOrValue::Value(true);
+        }
+        PromiseOrValue::Promise(
+            Promise::new(token_id.clone())
+                .function_call(
+                    "ft_transfer".to_string(),
+                    serde_json::json!({
+                        "receiver_id": self.owner_id,
+                        "amount": unused.to_string(),
+                    })
+                    .to_string()
+                    .into_bytes(),
+                    NearToken::from_yoctonear(1),
+                    Gas::from_gas(20_000_000_000_000),
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                        .on_reward_token_swept(pool_id, token_id, unused),
+                ),
+        )
+    }
+
+    /// Callback: After ft_transfer, put the swept amount back in the budget if it failed.
+    #[private]
+    pub fn on_reward_token_swept(
+        &mut self,
+        pool_id: PoolId,
+        token_id: AccountId,
+        amount: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                let mut pool = self.load_pool(pool_id);
+                if let Some(index) = pool.reward_token_index(&token_id) {
+                    pool.fund_reward_token(index, amount);
+                    self.save_pool(pool_id, &pool);
+                }
+                false
+            }
+        }
+    }
+
+    /// Claim the accrued rewards of a partner token
+    #[payable]
+    pub fn claim(&mut self, token_id: AccountId, pool_id: Option<PoolId>) -> Promise {
+        assert_one_yocto();
+        require!(!self.emergency_mode, "Emergency mode enabled");
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(!pool.pause_status.claim, "Claim paused");
+        let account_id = env::predecessor_account_id();
+        require!(
+            !self.denylist.contains(&account_id),
+            "Account is denylisted"
+        );
+        let index = pool
+            .reward_token_index(&token_id)
+            .expect("Reward token not found");
+        let stake_amount = pool
+            .stakes
+            .get(&account_id)
+            .map(|stake_info| stake_info.amount)
+            .unwrap_or(0);
+        pool.update_reward_tokens();
+        self.settle_reward_tokens(pool_id, &pool, &account_id, stake_amount);
+
+        let key = (pool_id, token_id.clone(), account_id.clone());
+        let mut account_reward = self.reward_accounts.get(&key).unwrap_or_default();
+        let amount = account_reward.unclaimed;
+        require!(amount > 0, "No reward to claim");
+        account_reward.unclaimed = 0;
+        self.reward_accounts.insert(&key, &account_reward);
+        pool.reward_tokens[index].claimed += amount;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Claim {} of {} for {}",
+            amount, token_id, account_id
+        ));
+        // The protocol fee is taken once the transfer goes through
+        let fee = self.protocol_fee(amount);
+
+        Promise::new(token_id.clone())
+            .function_call(
+                "ft_transfer".to_string(),
+                serde_json::json!({
+                    "receiver_id": account_id,
+                    "amount": (amount - fee).to_string(),
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_gas(20_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                    .on_claim_reward(pool_id, token_id, account_id, amount, fee),
+            )
+    }
+
+    /// Callback: After ft_transfer, take the protocol fee or credit the reward back if the
+    /// transfer failed.
+    #[private]
+    pub fn on_claim_reward(
+        &mut self,
+        pool_id: PoolId,
+        token_id: AccountId,
+        account_id: AccountId,
+        amount: u128,
+        fee: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => {
+                self.take_fee(&token_id, fee);
+                true
+            }
+            Err(_) => {
+                let key = (pool_id, token_id.clone(), account_id);
+                let mut account_reward = self.reward_accounts.get(&key).unwrap_or_default();
+                account_reward.unclaimed += amount;
+                self.reward_accounts.insert(&key, &account_reward);
+                let mut pool = self.load_pool(pool_id);
+                if let Some(index) = pool.reward_token_index(&token_id) {
+                    pool.reward_tokens[index].claimed -= amount;
+                    self.save_pool(pool_id, &pool);
+                }
+                false
+            }
+        }
+    }
+
+    /// Query partner reward tokens of a pool
+    pub fn get_reward_tokens(&self, pool_id: Option<PoolId>) -> Vec<RewardToken> {
+        self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID))
+            .reward_tokens
+    }
+
+    /// Query the unclaimed partner rewards of an account, including real-time accrual
+    pub fn get_unclaimed_rewards(
+        &self,
+        account_id: AccountId,
+        pool_id: Option<PoolId>,
+    ) -> Vec<(AccountId, u128)> {
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let pool = self.load_pool(pool_id);
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let stake_amount = pool
+            .stakes
+            .get(&account_id)
+            .map(|stake_info| stake_info.amount)
+            .unwrap_or(0);
+        pool.reward_tokens
+            .iter()
+            .map(|token| {
+                let (_, reward_per_share) = pool.accrue_reward_token(token, current_time);
+                let key = (pool_id, token.token_id.clone(), account_id.clone());
+                let account_reward = self.reward_accounts.get(&key).unwrap_or_default();
+                let unclaimed = account_reward.unclaimed
+                    + earned(
+                        stake_amount,
+                        reward_per_share,
+                        account_reward.reward_per_share_paid,
+                    );
+                (token.token_id.clone(), unclaimed)
+            })
+            .collect()
+    }
+}
+
+impl StakingContract {
+    /// Move the rewards earned by `stake_amount` into the unclaimed balance of an account.
+    /// Must run after `update_reward_tokens` and before the stake amount changes.
+    pub(crate) fn settle_reward_tokens(
+        &mut self,
+        pool_id: PoolId,
+        pool: &Pool,
+        account_id: &AccountId,
+        stake_amount: u128,
+    ) {
+        for token in pool.reward_tokens.iter() {
+            let key = (pool_id, token.token_id.clone(), account_id.clone());
+            let mut account_reward = self.reward_accounts.get(&key).unwrap_or_default();
+            account_reward.unclaimed += earned(
+                stake_amount,
+                token.reward_per_share,
+                account_reward.reward_per_share_paid,
+            );
+            account_reward.reward_per_share_paid = token.reward_per_share;
+            self.reward_accounts.insert(&key, &account_reward);
+        }
+    }
+}
+
+impl Pool {
+    pub(crate) fn reward_token_index(&self, token_id: &AccountId) -> Option<usize> {
+        self.reward_tokens
+            .iter()
+            .position(|token| &token.token_id == token_id)
+    }
+
+    /// Add tokens received from a reward token contract to its budget.
+    pub(crate) fn fund_reward_token(&mut self, index: usize, amount: u128) {
+        let token = &mut self.reward_tokens[index];
+        token.budget += amount;
+        env::log_str(&format!(
+            "Reward budget of {} updated to {}",
+            token.token_id, token.budget
+        ));
+    }
+
+    /// Funded budget of a reward token not claimed yet, kept out of `withdraw_token`.
+    pub(crate) fn reward_token_reserve(&self, token_id: &AccountId) -> u128 {
+        self.reward_tokens
+            .iter()
+            .filter(|token| &token.token_id == token_id)
+            .map(|token| token.budget - token.claimed)
+            .sum()
+    }
+
+    /// Advance the accumulators of all reward tokens to the current time.
+    /// Must run before `total_staked` changes.
+    pub(crate) fn update_reward_tokens(&mut self) {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        for index in 0..self.reward_tokens.len() {
+            let (emitted, reward_per_share) =
+                self.accrue_reward_token(&self.reward_tokens[index], current_time);
+            let token = &mut self.reward_tokens[index];
+            token.distributed += emitted;
+            token.reward_per_share = reward_per_share;
+            token.last_update = std::cmp::max(token.last_update, current_time);
+        }
+    }
+
+    /// Emission since the last update, capped by the unspent budget, and the resulting
+    /// accumulator. Nothing is emitted while nobody is staked.
+    fn accrue_reward_token(&self, token: &RewardToken, current_time: u64) -> (u128, u128) {
+        if current_time <= token.last_update || self.total_staked == 0 {
+            return (0, token.reward_per_share);
+        }
+        // Summed in U256, a rate set too high is capped by the budget instead of overflowing
+        let scheduled = token
+            .schedule
+            .iter()
+            .fold(U256::zero(), |scheduled, period| {
+                let start = std::cmp::max(period.start_time, token.last_update);
+                let end = std::cmp::min(period.end_time, current_time);
+                if end > start {
+                    scheduled + U256::from(period.reward_per_second.0) * U256::from(end - start)
+                } else {
+                    scheduled
+                }
+            });
+        let emitted =
+            std::cmp::min(scheduled, U256::from(token.budget - token.distributed)).as_u128();
+        let increment = U256::from(emitted) * U256::from(REWARD_PER_SHARE_PRECISION)
+            / U256::from(self.total_staked);
+        (emitted, token.reward_per_share + increment.as_u128())
+    }
+}
+
+/// Rewards earned by `amount` between two accumulator values.
+fn earned(amount: u128, reward_per_share: u128, reward_per_share_paid: u128) -> u128 {
+    (U256::from(amount) * U256::from(reward_per_share - reward_per_share_paid)
+        / U256::from(REWARD_PER_SHARE_PRECISION))
+    .as_u128()
+}
+
+fn assert_valid_schedule(schedule: &[RatePeriod]) {
+    for period in schedule {
+        require!(
+            period.end_time > period.start_time,
+            "Period end time should gt start time"
+        );
+    }
+}
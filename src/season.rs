@@ -0,0 +1,228 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::pool::{assert_valid_pool_settings, Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, UserOperationState, NANOSECONDS};
+
+/// Season window and the rewards earned in it
+#[near(serializers = [json])]
+pub struct SeasonView {
+    pool_id: PoolId,
+    start_time: u64,
+    end_time: u64,
+    total_reward: U128,                   // Season budget
+    total_staked: U128,                   // Principal still staked in the season
+    rewards_earned: U128,                 // Rewards paid or rolled over, plus accrued and unpaid
+    account_rewards_earned: Option<U128>, // Same for the queried account
+}
+
+#[near]
+impl StakingContract {
+    /// Add the season following `pool_id` (only callable by the owner).
+    /// The season is a new pool staking the same token with its own rate schedule and budget.
+    /// - `pool_id`: Last season, it must have an end time.
+    /// - `start_time`, `end_time`: Season window, starting at or after the end of `pool_id`.
+    /// - `early_aar`, `aar`: AAR of each week after `start_time`, then once they are over.
+    /// - `total_reward`: Season budget.
+    #[payable]
+    pub fn add_season(
+        &mut self,
+        pool_id: PoolId,
+        start_time: u64,
+        end_time: u64,
+        early_aar: Vec<u128>,
+        aar: u128,
+        total_reward: U128,
+    ) -> PoolId {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can add season."
+        );
+        let mut previous = self.load_pool(pool_id);
+        require!(
+            previous.next_season.is_none(),
+            "Season already has a next season"
+        );
+        require!(previous.stake_end_time != 0, "Season has no end time");
+        require!(
+            start_time >= previous.stake_end_time,
+            "Season cannot start before the previous one ends"
+        );
+        require!(end_time > start_time, "End time should gt start time");
+        assert_valid_pool_settings(total_reward.0, previous.lock_duration, &early_aar, aar);
+
+        let season_id = self.pools.len() as PoolId;
+        let mut season = Pool::new(
+            season_id,
+            previous.staked_token.clone(),
+            total_reward.0,
+            previous.lock_duration,
+            early_aar,
+            aar,
+        );
+        season.stake_start_time = start_time;
+        season.stake_end_time = end_time;
+        season.previous_season = Some(pool_id);
+        previous.next_season = Some(season_id);
+        self.save_pool(season_id, &season);
+        self.save_pool(pool_id, &previous);
+        env::log_str(&format!(
+            "Season {} added after {} from {} to {}",
+            season_id, pool_id, start_time, end_time
+        ));
+        season_id
+    }
+
+    /// Roll the position of the caller in an ended season into the next season.
+    /// Rewards earned in the ended season are added to the principal.
+    /// Exiting instead is done with `unstake`, which no longer applies the lock.
+    #[payable]
+    pub fn rollover(&mut self, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        require!(!self.emergency_mode, "Emergency mode enabled");
+        let account_id = env::predecessor_account_id();
+        require!(self.can_stake(&account_id), "Account cannot stake");
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        require!(pool.has_ended(current_time), "Season has not ended");
+        let next_id = pool
+            .next_season
+            .unwrap_or_else(|| env::panic_str("No next season"));
+        let stake_info = pool
+            .stakes
+            .get(&account_id)
+            .expect("No stake found for this account");
+        require!(!pool.pause_status.unstake, "Unstake paused");
+        let in_progress = matches!(
+            self.user_states.get(&account_id),
+            Some(UserOperationState::Staking | UserOperationState::Unstaking)
+        );
+        require!(
+            !in_progress || self.is_user_state_stale(&account_id),
+            "Operation already in progress."
+        );
+
+        // Settle the ended season as an unstake without lock
+        self.checkpoint_account(pool_id, &mut pool, &account_id, stake_info.amount);
+        let owed_reward = stake_info.accumulated_reward
+            + self.settle_boosted_reward(pool_id, &mut pool, &account_id, &stake_info);
+        // While claims are paused only the principal rolls over, the reward stays frozen in
+        // the ended season until `withdraw_frozen_reward`
+        let reward_amount = if pool.pause_status.claim {
+            self.freeze_reward(pool_id, &account_id, owed_reward);
+            0
+        } else {
+            let reward_amount = pool.apply_haircut(owed_reward);
+            pool.release_liability(owed_reward);
+            reward_amount
+        };
+        pool.stakes.remove(&account_id);
+        self.update_loyalty(pool_id, &pool, &account_id, 0);
+        self.track_position(pool_id, &account_id, 0);
+        pool.total_staked -= stake_info.amount;
+        pool.total_claimed_reward += reward_amount;
+        self.reward_referrer(pool_id, &mut pool, &account_id, reward_amount);
+        // In a vesting pool the reward vests in the ended season and only the principal rolls over
+        let fee = self.protocol_fee(reward_amount);
+        let vested = if pool.vesting.is_some() {
+            reward_amount - fee
+        } else {
+            0
+        };
+        self.vest_reward(pool_id, &mut pool, &account_id, vested);
+        self.save_pool(pool_id, &pool);
+        self.record_earned_reward(pool_id, &account_id, reward_amount);
+
+        // Stake principal and rewards in the next season, the tokens stay in the contract
+        let mut next = self.load_pool(next_id);
+        require!(!next.pause_status.stake, "Stake paused");
+        require!(!next.finalized, "Pool is finalized");
+        self.take_fee(&pool.staked_token, fee);
+        let amount = stake_info.amount + reward_amount - fee - vested;
+        let staked = next
+            .stakes
+            .get(&account_id)
+            .map(|stake_info| stake_info.amount)
+            .unwrap_or(0);
+        require!(amount <= next.stake_room(staked), "Stake cap exceeded");
+        require!(staked + amount >= next.min_stake, "Below minimum stake");
+        self.add_stake(next_id, &mut next, &account_id, amount);
+        self.save_pool(next_id, &next);
+        self.set_user_state(&account_id, UserOperationState::Idle);
+        env::log_str(&format!(
+            "Rollover of {} for {} from season {} to {}",
+            amount, account_id, pool_id, next_id
+        ));
+    }
+
+    /// Query the seasons `pool_id` belongs to, from the first to the last.
+    /// - `account_id`: Also report the rewards earned by this account in each season.
+    pub fn get_seasons(
+        &self,
+        pool_id: Option<PoolId>,
+        account_id: Option<AccountId>,
+    ) -> Vec<SeasonView> {
+        let mut season_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut season = self.load_pool(season_id);
+        while let Some(previous_id) = season.previous_season {
+            season_id = previous_id;
+            season = self.load_pool(season_id);
+        }
+        let mut seasons = vec![self.season_view(season_id, &season, account_id.as_ref())];
+        while let Some(next_id) = season.next_season {
+            season_id = next_id;
+            season = self.load_pool(season_id);
+            seasons.push(self.season_view(season_id, &season, account_id.as_ref()));
+        }
+        seasons
+    }
+}
+
+impl StakingContract {
+    /// Add a reward paid out or rolled over to what an account earned in a pool.
+    pub(crate) fn record_earned_reward(
+        &mut self,
+        pool_id: PoolId,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let key = (pool_id, account_id.clone());
+        let earned = self.earned_rewards.get(&key).unwrap_or(0);
+        self.earned_rewards.insert(&key, &(earned + amount));
+    }
+
+    fn season_view(
+        &self,
+        pool_id: PoolId,
+        pool: &Pool,
+        account_id: Option<&AccountId>,
+    ) -> SeasonView {
+        let account_rewards_earned = account_id.map(|account_id| {
+            let earned = self
+                .earned_rewards
+                .get(&(pool_id, account_id.clone()))
+                .unwrap_or(0);
+            let accrued = pool
+                .stakes
+                .get(account_id)
+                .map(|stake_info| stake_info.accumulated_reward + pool.pending_reward(&stake_info))
+                .unwrap_or(0);
+            U128(earned + accrued)
+        });
+        SeasonView {
+            pool_id,
+            start_time: pool.stake_start_time,
+            end_time: pool.stake_end_time,
+            total_reward: U128(pool.total_reward),
+            total_staked: U128(pool.total_staked),
+            rewards_earned: U128(pool.total_claimed_reward + pool.current_liability()),
+            account_rewards_earned,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+
+use near_sdk::json_types::U128;
+use near_sdk::{env, near, AccountId};
+
+use crate::events::RefundReason;
+use crate::pool::{Pool, PoolId};
+use crate::{StakeInfo, StakingContract, NANOSECONDS};
+
+/// Maximum number of positions seeded by one transfer
+pub const MAX_SEED_BATCH: usize = 100;
+
+/// Position migrated from a previous staking contract
+#[near(serializers = [json])]
+pub struct SeedRecord {
+    pub(crate) account_id: AccountId,
+    pub(crate) amount: U128,             // Principal
+    pub(crate) first_stake_time: u64,    // Lock and loyalty count from this time
+    pub(crate) accumulated_reward: U128, // Reward owed by the previous contract
+}
+
+impl StakingContract {
+    /// Create the positions of a batch backed by a deposit of `amount`, which must be
+    /// the sum of their principal and accumulated rewards. Rewards accrue in the pool
+    /// from now on. Nothing is created if any record is rejected. The caller saves the pool.
+    pub(crate) fn seed_stakes(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        records: Vec<SeedRecord>,
+        amount: u128,
+    ) -> Result<(), RefundReason> {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let mut accounts = HashSet::new();
+        let valid = !records.is_empty()
+            && records.len() <= MAX_SEED_BATCH
+            && records.iter().all(|record| {
+                record.amount.0 >= std::cmp::max(pool.min_stake, 1)
+                    && record.first_stake_time <= current_time
+                    && pool.stakes.get(&record.account_id).is_none()
+                    && accounts.insert(record.account_id.clone())
+            });
+        if !valid {
+            return Err(RefundReason::InvalidSeed);
+        }
+        let total = records.iter().try_fold(0u128, |total, record| {
+            total.checked_add(record.amount.0 + record.accumulated_reward.0)
+        });
+        if total != Some(amount) {
+            return Err(RefundReason::SeedTotalMismatch);
+        }
+
+        for record in records {
+            self.checkpoint_account(pool_id, pool, &record.account_id, 0);
+            pool.stakes.insert(
+                &record.account_id,
+                &StakeInfo {
+                    amount: record.amount.0,
+                    accumulated_reward: record.accumulated_reward.0,
+                    first_stake_time: record.first_stake_time,
+                    start_time: current_time,
+                },
+            );
+            self.update_loyalty(pool_id, pool, &record.account_id, record.amount.0);
+            self.track_position(pool_id, &record.account_id, record.amount.0);
+            pool.total_staked += record.amount.0;
+            // Migrated rewards are deposited with the principal and owed right away
+            pool.total_reward += record.accumulated_reward.0;
+            pool.accrued_reward_liability += record.accumulated_reward.0;
+        }
+        env::log_str(&format!(
+            "Seeded {} positions in pool {} with {}",
+            accounts.len(),
+            pool_id,
+            amount
+        ));
+        Ok(())
+    }
+}
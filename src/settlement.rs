@@ -0,0 +1,115 @@
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, Promise};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, UserOperationState, NANOSECONDS};
+
+/// Maximum number of positions settled by one `force_unstake_range`
+pub const MAX_FORCE_UNSTAKE_BATCH: u64 = 10;
+
+#[near]
+impl StakingContract {
+    /// Settle and pay out the position of an account in a pool that has ended
+    /// (only callable by the owner). Rewards follow the rules of `unstake`.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn force_unstake(&mut self, account_id: AccountId, pool_id: Option<PoolId>) -> Promise {
+        assert_one_yocto();
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_ended_pool(pool_id);
+        let promise = self
+            .force_unstake_account(pool_id, &mut pool, account_id)
+            .unwrap_or_else(|| env::panic_str("Operation already in progress."));
+        self.save_pool(pool_id, &pool);
+        promise
+    }
+
+    /// Settle and pay out the positions of a pool that has ended, starting at `from_index`
+    /// (only callable by the owner). Settled positions are removed from the pool, so the
+    /// next call starts at the same index. Accounts with an operation in progress are skipped.
+    /// - `limit`: Number of positions, at most MAX_FORCE_UNSTAKE_BATCH.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    /// Returns the number of positions settled.
+    #[payable]
+    pub fn force_unstake_range(
+        &mut self,
+        from_index: u64,
+        limit: u64,
+        pool_id: Option<PoolId>,
+    ) -> u64 {
+        assert_one_yocto();
+        require!(
+            limit <= MAX_FORCE_UNSTAKE_BATCH,
+            "Cannot exceed MAX_FORCE_UNSTAKE_BATCH"
+        );
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_ended_pool(pool_id);
+        let account_ids: Vec<AccountId> = pool
+            .stakes
+            .keys()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect();
+        let settled = account_ids
+            .into_iter()
+            .filter_map(|account_id| self.force_unstake_account(pool_id, &mut pool, account_id))
+            .count();
+        self.save_pool(pool_id, &pool);
+        settled as u64
+    }
+}
+
+impl StakingContract {
+    /// Load a pool for a forced unstake, checking the caller and the end of the pool.
+    fn load_ended_pool(&self, pool_id: PoolId) -> Pool {
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can force unstake."
+        );
+        require!(
+            !self.emergency_mode,
+            "Emergency mode enabled, use emergency_withdraw"
+        );
+        let pool = self.load_pool(pool_id);
+        require!(
+            pool.has_ended(env::block_timestamp() / NANOSECONDS),
+            "Pool has not ended"
+        );
+        pool
+    }
+
+    /// Settle the whole position of an account and send its payout, a failed transfer
+    /// is recorded as a pending payout. `None` if the account has an operation in progress.
+    fn force_unstake_account(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: AccountId,
+    ) -> Option<Promise> {
+        let stake_info = pool
+            .stakes
+            .get(&account_id)
+            .expect("No stake found for this account");
+        let in_progress = matches!(
+            self.user_states.get(&account_id),
+            Some(UserOperationState::Staking | UserOperationState::Unstaking)
+        );
+        if in_progress && !self.is_user_state_stale(&account_id) {
+            return None;
+        }
+        self.set_user_state(&account_id, UserOperationState::Unstaking);
+        let amount = stake_info.amount;
+        let (_, payout) = self.settle_unstake(pool_id, pool, &account_id, stake_info, amount);
+        self.complete_payout(pool_id, pool, &account_id, &payout);
+        env::log_str(&format!(
+            "Force unstake of {} for {} in pool {}",
+            amount, account_id, pool_id
+        ));
+        Some(self.send_payout(
+            pool_id,
+            &pool.staked_token.clone(),
+            account_id,
+            payout.total,
+        ))
+    }
+}
@@ -0,0 +1,327 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, Gas, NearToken, Promise};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{
+    StakeInfo, StakingContract, StakingContractExt, AAR_BASE, NANOSECONDS, SECONDS_IN_A_YEAR, U256,
+};
+
+/// Progress of rebuilding the liability of the default pool after `migrate`
+#[near(serializers = [borsh])]
+pub struct LiabilityRecompute {
+    remaining: u64, // Positions left to scan, from the end of the stakes down to index 0
+    snapshot_time: u64, // Positions add what they accrued up to this time
+}
+
+impl LiabilityRecompute {
+    pub(crate) fn new(positions: u64, snapshot_time: u64) -> Self {
+        Self {
+            remaining: positions,
+            snapshot_time,
+        }
+    }
+}
+
+/// Token balance compared with what a pool owes
+#[near(serializers = [json])]
+pub struct Solvency {
+    token_balance: Option<U128>, // Contract token balance, only known to `check_solvency`
+    total_staked: U128,          // Principal owed to stakers
+    total_pending_payout: U128,  // Failed payouts owed to accounts
+    accrued_reward_liability: U128, // Rewards accrued but not yet paid
+    remaining_reward_budget: U128, // total_reward - total_claimed_reward
+    owed: U128,                  // Principal, pending payouts, vesting and payable accrued rewards
+    token_owed: U128,            // Owed in the staked token by every pool and reward budget
+    is_solvent: Option<bool>,    // token_balance >= token_owed
+}
+
+#[near]
+impl StakingContract {
+    /// Query the liabilities of a pool against its reward budget.
+    /// Views cannot read the token balance, use `check_solvency` to include it.
+    pub fn get_solvency(&self, pool_id: Option<PoolId>) -> Solvency {
+        self.solvency(pool_id.unwrap_or(DEFAULT_POOL_ID), None)
+    }
+
+    /// Read the token balance and compare it with the liabilities of a pool.
+    pub fn check_solvency(&self, pool_id: Option<PoolId>) -> Promise {
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        Promise::new(self.load_pool(pool_id).staked_token)
+            .function_call(
+                "ft_balance_of".to_string(),
+                serde_json::json!({
+                    "account_id": env::current_account_id()
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_near(0),
+                Gas::from_gas(10_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                    .on_check_solvency(pool_id),
+            )
+    }
+
+    /// Count the next `limit` positions of the default pool into its liability after
+    /// `migrate` (only callable by the owner). Positions of the default pool cannot change
+    /// until every one is counted. Returns the number of positions left.
+    #[payable]
+    pub fn recompute_liability(&mut self, limit: u64) -> u64 {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can recompute liability."
+        );
+        let mut recompute = self
+            .liability_recompute
+            .take()
+            .unwrap_or_else(|| env::panic_str("No liability recompute in progress"));
+        let mut pool = self.load_pool(DEFAULT_POOL_ID);
+        // Scanning from the end keeps the unscanned positions at the front: a removal moves
+        // the last position into its slot, which is either unscanned or already marked counted
+        let remaining = std::cmp::min(recompute.remaining, pool.stakes.len());
+        let start = remaining.saturating_sub(limit);
+        let scanned: Vec<(AccountId, StakeInfo)> = pool
+            .stakes
+            .iter()
+            .skip(start as usize)
+            .take((remaining - start) as usize)
+            .collect();
+        let mut counted = 0;
+        for (account_id, stake_info) in &scanned {
+            if self.liability_counted.insert(account_id) {
+                pool.accrued_reward_liability += stake_info.accumulated_reward
+                    + pool
+                        .snapshot_reward(stake_info, recompute.snapshot_time)
+                        .unwrap_or_else(|| env::panic_str("Reward overflow"));
+                counted += 1;
+            }
+        }
+        recompute.remaining = start;
+        self.save_pool(DEFAULT_POOL_ID, &pool);
+        if start > 0 {
+            self.liability_recompute = Some(recompute);
+        }
+        env::log_str(&format!(
+            "Liability recomputed for {} positions, {} left",
+            counted, start
+        ));
+        start
+    }
+
+    /// Callback: After ft_balance_of, report solvency with the token balance.
+    #[private]
+    pub fn on_check_solvency(
+        &self,
+        pool_id: PoolId,
+        #[callback_result] call_result: Result<U128, near_sdk::PromiseError>,
+    ) -> Solvency {
+        let balance = call_result.unwrap_or_else(|_| env::panic_str("Failed to get token balance"));
+        self.solvency(pool_id, Some(balance.0))
+    }
+
+    /// Query the share of owed rewards currently paid out, in basis points.
+    /// Below 10000 when accrued rewards exceed the remaining budget.
+    pub fn get_haircut_factor(&self, pool_id: Option<PoolId>) -> u128 {
+        let pool = self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID));
+        let liability = pool.current_liability();
+        let remaining = pool.remaining_reward_budget();
+        if liability <= remaining {
+            AAR_BASE
+        } else {
+            (U256::from(remaining) * U256::from(AAR_BASE) / U256::from(liability)).as_u128()
+        }
+    }
+
+    /// Query rewards accrued by all stakers of a pool and not yet paid
+    pub fn get_accrued_reward_liability(&self, pool_id: Option<PoolId>) -> u128 {
+        self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID))
+            .current_liability()
+    }
+}
+
+impl StakingContract {
+    /// Reject changes to the positions of a pool while `recompute_liability` is counting them.
+    pub(crate) fn assert_liability_counted(&self, pool_id: PoolId) {
+        require!(
+            pool_id != DEFAULT_POOL_ID || self.liability_recompute.is_none(),
+            "Liability recompute in progress"
+        );
+    }
+
+    /// Count a position leaving the default pool during `recompute_liability` if it was not
+    /// counted yet, so that writing it off does not take from the other positions.
+    /// Only saturating arithmetic is used, a reward that overflows is left out.
+    pub(crate) fn count_leaving_position(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        stake_info: &StakeInfo,
+    ) {
+        let Some(recompute) = self.liability_recompute.as_ref() else {
+            return;
+        };
+        if pool_id != DEFAULT_POOL_ID || self.liability_counted.remove(account_id) {
+            return;
+        }
+        let snapshot_reward = pool
+            .snapshot_reward(stake_info, recompute.snapshot_time)
+            .unwrap_or(0);
+        pool.accrued_reward_liability = pool
+            .accrued_reward_liability
+            .saturating_add(stake_info.accumulated_reward)
+            .saturating_add(snapshot_reward);
+    }
+
+    /// Tokens of `token_id` reserved by every pool except `pool_id`: principal, pending
+    /// payouts, vesting rewards, airdrop reserves and the full remaining budget of pools staking it, plus
+    /// unclaimed reward token budgets, the referral budgets of all pools staking it and
+    /// uncollected fees.
+    pub(crate) fn reserved_balance(&self, token_id: &AccountId, pool_id: PoolId) -> u128 {
+        let pools: u128 = self
+            .pools
+            .iter()
+            .map(|(id, pool)| {
+                let staked = if &pool.staked_token != token_id {
+                    0
+                } else if id != pool_id {
+                    pool.total_staked
+                        + pool.total_pending_payout
+                        + pool.remaining_reward_budget()
+                        + pool.referral_budget
+                        + pool.total_vesting
+                        + pool.airdrop_reserve
+                } else {
+                    pool.referral_budget
+                };
+                staked + pool.reward_token_reserve(token_id)
+            })
+            .sum();
+        pools + self.uncollected_fees(token_id)
+    }
+
+    /// Tokens of `token_id` owed right now by every pool, plus uncollected fees.
+    fn token_owed(&self, token_id: &AccountId) -> u128 {
+        let pools: u128 = self
+            .pools
+            .values()
+            .map(|pool| {
+                let staked = if &pool.staked_token == token_id {
+                    pool.owed_balance()
+                } else {
+                    0
+                };
+                staked + pool.reward_token_reserve(token_id)
+            })
+            .sum();
+        pools + self.uncollected_fees(token_id)
+    }
+
+    fn solvency(&self, pool_id: PoolId, token_balance: Option<u128>) -> Solvency {
+        let pool = self.load_pool(pool_id);
+        let token_owed = self.token_owed(&pool.staked_token);
+        Solvency {
+            token_balance: token_balance.map(U128),
+            total_staked: U128(pool.total_staked),
+            total_pending_payout: U128(pool.total_pending_payout),
+            accrued_reward_liability: U128(pool.current_liability()),
+            remaining_reward_budget: U128(pool.remaining_reward_budget()),
+            owed: U128(pool.owed_balance()),
+            token_owed: U128(token_owed),
+            is_solvent: token_balance.map(|balance| balance >= token_owed),
+        }
+    }
+}
+
+impl Pool {
+    /// Accrue rewards of all stakers up to the current reward end time.
+    pub(crate) fn update_liability(&mut self) {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let reward_end_time = self.reward_end_time(current_time);
+        if reward_end_time > self.liability_updated_at {
+            self.accrued_reward_liability += self.calculate_reward(
+                self.total_staked,
+                reward_end_time,
+                self.liability_updated_at,
+            );
+            self.liability_updated_at = reward_end_time;
+        }
+    }
+
+    /// Reward a position accrued up to `snapshot_time`, rounded up so that the sum of
+    /// positions stays at or below the global figure. `None` on overflow.
+    fn snapshot_reward(&self, stake_info: &StakeInfo, snapshot_time: u64) -> Option<u128> {
+        let start_time = std::cmp::min(stake_info.start_time, snapshot_time);
+        self.checked_scaled_reward(stake_info.amount, snapshot_time, start_time)
+            .map(|scaled| scaled.div_ceil(SECONDS_IN_A_YEAR * AAR_BASE))
+    }
+
+    /// Remove rewards that were paid out or forfeited from the liability.
+    /// Per-position rounding keeps the sum of positions at or below the global figure.
+    pub(crate) fn release_liability(&mut self, amount: u128) {
+        self.accrued_reward_liability = self.accrued_reward_liability.saturating_sub(amount);
+    }
+
+    /// Write a position off the liability without settling it, for emergency withdrawals.
+    /// Only saturating arithmetic is used: what it accrued since the last update is never
+    /// added once it leaves `total_staked`, and a reward that overflows stays in the liability.
+    pub(crate) fn write_off_position(&mut self, stake_info: &StakeInfo) {
+        let start_time = std::cmp::min(stake_info.start_time, self.liability_updated_at);
+        let accrued = self
+            .checked_scaled_reward(stake_info.amount, self.liability_updated_at, start_time)
+            .map_or(0, |scaled| scaled / (SECONDS_IN_A_YEAR * AAR_BASE));
+        self.release_liability(stake_info.accumulated_reward.saturating_add(accrued));
+        self.total_staked = self.total_staked.saturating_sub(stake_info.amount);
+    }
+
+    /// Liability including rewards accrued since the last update.
+    pub(crate) fn current_liability(&self) -> u128 {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let reward_end_time = self.reward_end_time(current_time);
+        if reward_end_time > self.liability_updated_at {
+            self.accrued_reward_liability
+                + self.calculate_reward(
+                    self.total_staked,
+                    reward_end_time,
+                    self.liability_updated_at,
+                )
+        } else {
+            self.accrued_reward_liability
+        }
+    }
+
+    /// Scale an owed reward by remaining budget / liability when the budget cannot cover
+    /// every staker, so each one takes the same share instead of first come first served.
+    /// Expects the liability to be updated.
+    pub(crate) fn apply_haircut(&self, owed_reward: u128) -> u128 {
+        let liability = self.accrued_reward_liability;
+        let remaining = self.remaining_reward_budget();
+        if liability <= remaining {
+            return owed_reward;
+        }
+        let scaled = U256::from(owed_reward) * U256::from(remaining) / U256::from(liability);
+        // The liability is at least the sum of positions, the cap only guards rounding.
+        std::cmp::min(scaled.as_u128(), remaining)
+    }
+
+    /// Remaining reward budget, total_reward - total_claimed_reward.
+    pub(crate) fn remaining_reward_budget(&self) -> u128 {
+        self.total_reward.saturating_sub(self.total_claimed_reward)
+    }
+
+    /// Tokens the pool owes right now: principal, pending payouts, vesting rewards, airdrop
+    /// reserves and accrued rewards, which can never exceed the remaining budget.
+    pub(crate) fn owed_balance(&self) -> u128 {
+        let payable_reward =
+            std::cmp::min(self.current_liability(), self.remaining_reward_budget());
+        self.total_staked
+            + self.total_pending_payout
+            + self.total_vesting
+            + self.airdrop_reserve
+            + payable_reward
+    }
+}
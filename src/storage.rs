@@ -0,0 +1,303 @@
+use near_contract_standards::storage_management::StorageBalance;
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, AccountId, Gas, NearToken, Promise, PromiseOrValue};
+
+use crate::pool::{Pool, PoolId};
+use crate::{StakeInfo, StakingContract, StakingContractExt, UserOperationState, NANOSECONDS};
+
+/// Default NEAR attached to `storage_deposit` when sponsoring a registration (0.00125 NEAR).
+pub const DEFAULT_STORAGE_DEPOSIT: u128 = 1_250_000_000_000_000_000_000;
+
+/// How `unstake` handles a receiver that is not registered on the token contract
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageRegistrationMode {
+    Reject,  // Fail the unstake and keep the stake
+    Sponsor, // Register the receiver out of the storage budget
+}
+
+/// Amounts settled by an unstake, paid once the receiver is registered
+#[near(serializers = [json])]
+pub struct UnstakePayout {
+    pub(crate) principal: u128, // Principal unstaked
+    pub(crate) withdrawn: u128, // Reward taken out of the position: paid, forfeited or frozen
+    pub(crate) reward: u128,    // Reward settled, before the protocol fee
+    pub(crate) fee: u128,       // Protocol fee taken from the reward
+    pub(crate) vested: u128,    // Reward net of the fee moved into vesting
+    pub(crate) frozen: u128,    // Reward frozen for a denylisted account, not paid
+    pub(crate) total: u128,     // Amount transferred to the account
+}
+
+/// Storage registration settings
+#[near(serializers = [json])]
+pub struct StorageRegistrationConfig {
+    mode: StorageRegistrationMode,
+    budget: U128,         // NEAR available to sponsor registrations
+    deposit_amount: U128, // NEAR attached to each sponsored storage_deposit
+}
+
+#[near]
+impl StakingContract {
+    /// Set how unregistered receivers are handled (only callable by the owner).
+    /// - `mode`: Reject the unstake or sponsor the registration.
+    #[payable]
+    pub fn set_storage_registration_mode(&mut self, mode: StorageRegistrationMode) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set storage registration mode."
+        );
+        self.storage_registration_mode = mode;
+        env::log_str(&format!(
+            "Storage registration mode updated to {:?}",
+            self.storage_registration_mode
+        ));
+    }
+
+    /// Set the NEAR attached to each sponsored storage_deposit (only callable by the owner).
+    /// - `amount`: Deposit in yoctoNEAR.
+    #[payable]
+    pub fn set_storage_deposit_amount(&mut self, amount: U128) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set storage deposit amount."
+        );
+        self.storage_deposit_amount = amount.0;
+        env::log_str(&format!(
+            "Storage deposit amount updated to {}",
+            self.storage_deposit_amount
+        ));
+    }
+
+    /// Add the attached NEAR to the budget used to sponsor registrations.
+    #[payable]
+    pub fn fund_storage_budget(&mut self) {
+        let amount = env::attached_deposit().as_yoctonear();
+        assert!(amount > 0, "Attach NEAR to fund the storage budget");
+        self.storage_budget += amount;
+        env::log_str(&format!(
+            "Storage budget updated to {}",
+            self.storage_budget
+        ));
+    }
+
+    /// Query storage registration settings
+    pub fn get_storage_registration(&self) -> StorageRegistrationConfig {
+        StorageRegistrationConfig {
+            mode: self.storage_registration_mode.clone(),
+            budget: U128(self.storage_budget),
+            deposit_amount: U128(self.storage_deposit_amount),
+        }
+    }
+
+    /// Callback: After storage_balance_of, send the payout or reject the unstake.
+    #[private]
+    pub fn on_storage_balance_checked(
+        &mut self,
+        pool_id: PoolId,
+        account_id: AccountId,
+        stake_info: StakeInfo,
+        payout: UnstakePayout,
+        #[callback_result] call_result: Result<Option<StorageBalance>, near_sdk::PromiseError>,
+    ) -> PromiseOrValue<bool> {
+        let mut pool = self.load_pool(pool_id);
+        // A failed query is not treated as unregistered, the pending ledger covers a failed transfer.
+        let registered = !matches!(call_result, Ok(None));
+        let sponsored = !registered
+            && self.storage_registration_mode == StorageRegistrationMode::Sponsor
+            && self.storage_budget >= self.storage_deposit_amount;
+        if registered || sponsored {
+            // The unstake goes through
+            self.complete_payout(pool_id, &mut pool, &account_id, &payout);
+            self.save_pool(pool_id, &pool);
+        }
+        let total = payout.total;
+        if registered {
+            return PromiseOrValue::Promise(self.send_payout(
+                pool_id,
+                &pool.staked_token,
+                account_id,
+                total,
+            ));
+        }
+        if sponsored {
+            let deposit = self.storage_deposit_amount;
+            self.storage_budget -= deposit;
+            env::log_str(&format!("Sponsor storage registration of {}", account_id));
+            let register = Promise::new(pool.staked_token.clone()).function_call(
+                "storage_deposit".to_string(),
+                serde_json::json!({
+                    "account_id": account_id,
+                    "registration_only": true,
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(deposit),
+                Gas::from_gas(10_000_000_000_000),
+            );
+            return PromiseOrValue::Promise(
+                register.then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(Gas::from_gas(35_000_000_000_000))
+                        .on_storage_sponsored(pool_id, account_id, total, deposit),
+                ),
+            );
+        }
+
+        // The position was settled again since the unstake, by `emergency_withdraw` or a
+        // `force_unstake` after the state went stale: the unstake goes through instead and
+        // its payout is owed until the account registers
+        let remaining = stake_info.amount - payout.principal;
+        let current = pool.stakes.get(&account_id);
+        let current_amount = current.as_ref().map_or(0, |stake_info| stake_info.amount);
+        if current_amount != remaining {
+            self.complete_payout(pool_id, &mut pool, &account_id, &payout);
+            self.save_pool(pool_id, &pool);
+            self.set_user_state(&account_id, UserOperationState::Idle);
+            self.credit_pending_payout(pool_id, &account_id, total);
+            return PromiseOrValue::Value(false);
+        }
+
+        // Undo the unstake from the current position: the principal and the reward it took
+        // out go back, the position left by a partial unstake is settled up to now first
+        self.checkpoint_account(pool_id, &mut pool, &account_id, current_amount);
+        let accumulated_reward = current.map_or(0, |current| {
+            current.accumulated_reward
+                + self.settle_boosted_reward(pool_id, &mut pool, &account_id, &current)
+        });
+        // A frozen reward released by `remove_from_denylist` in the meantime is already paid
+        let unfrozen = self.unfreeze_reward(pool_id, &account_id, payout.frozen);
+        let restored_reward = payout.withdrawn - payout.frozen + unfrozen;
+        pool.accrued_reward_liability += payout.withdrawn - payout.frozen;
+        pool.total_staked += payout.principal;
+        pool.total_claimed_reward -= payout.reward;
+        pool.stakes.insert(
+            &account_id,
+            &StakeInfo {
+                amount: stake_info.amount,
+                accumulated_reward: accumulated_reward + restored_reward,
+                first_stake_time: stake_info.first_stake_time,
+                start_time: env::block_timestamp() / NANOSECONDS,
+            },
+        );
+        self.update_loyalty(pool_id, &pool, &account_id, stake_info.amount);
+        self.track_position(pool_id, &account_id, stake_info.amount);
+        self.save_pool(pool_id, &pool);
+        let key = (pool_id, account_id.clone());
+        let earned = self.earned_rewards.get(&key).unwrap_or(0);
+        self.earned_rewards.insert(&key, &(earned - payout.reward));
+        self.set_user_state(&account_id, UserOperationState::Idle);
+        env::log_str(&format!(
+            "Unstake rejected: {} is not registered on {}",
+            account_id, pool.staked_token
+        ));
+        PromiseOrValue::Value(false)
+    }
+
+    /// Callback: After a sponsored storage_deposit, send the payout. If the registration
+    /// failed the deposit is refunded to the contract and goes back into the storage budget,
+    /// and the payout is recorded as pending.
+    #[private]
+    pub fn on_storage_sponsored(
+        &mut self,
+        pool_id: PoolId,
+        account_id: AccountId,
+        payout: u128,
+        deposit: u128,
+        #[callback_result] call_result: Result<StorageBalance, near_sdk::PromiseError>,
+    ) -> PromiseOrValue<bool> {
+        if call_result.is_ok() {
+            let token_id = self.load_pool(pool_id).staked_token;
+            return PromiseOrValue::Promise(
+                self.send_payout(pool_id, &token_id, account_id, payout),
+            );
+        }
+        self.storage_budget += deposit;
+        env::log_str(&format!(
+            "Sponsored storage registration of {} failed, {} returned to the storage budget",
+            account_id, deposit
+        ));
+        self.set_user_state(&account_id, UserOperationState::Idle);
+        self.credit_pending_payout(pool_id, &account_id, payout);
+        PromiseOrValue::Value(false)
+    }
+}
+
+impl StakingContract {
+    /// Check the receiver is registered on the staked token, then send the payout.
+    pub(crate) fn check_storage_then_payout(
+        &self,
+        pool_id: PoolId,
+        staked_token: AccountId,
+        account_id: AccountId,
+        stake_info: StakeInfo,
+        payout: UnstakePayout,
+    ) -> Promise {
+        Promise::new(staked_token)
+            .function_call(
+                "storage_balance_of".to_string(),
+                serde_json::json!({ "account_id": account_id })
+                    .to_string()
+                    .into_bytes(),
+                NearToken::from_near(0),
+                Gas::from_gas(5_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(50_000_000_000_000))
+                    .on_storage_balance_checked(pool_id, account_id, stake_info, payout),
+            )
+    }
+
+    /// Apply the parts of a payout that only happen once it goes through: the referrer earns
+    /// its share of the rewards, the vesting share vests and the protocol fee is taken.
+    /// The caller saves the pool.
+    pub(crate) fn complete_payout(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        payout: &UnstakePayout,
+    ) {
+        self.reward_referrer(pool_id, pool, account_id, payout.reward);
+        self.vest_reward(pool_id, pool, account_id, payout.vested);
+        self.take_fee(&pool.staked_token, payout.fee);
+    }
+
+    /// Transfer a payout, recorded as pending if the transfer fails.
+    pub(crate) fn send_payout(
+        &self,
+        pool_id: PoolId,
+        token_id: &AccountId,
+        account_id: AccountId,
+        payout: u128,
+    ) -> Promise {
+        transfer_payout(token_id, &account_id, payout)
+            .then(Self::on_payout_callback(pool_id, account_id, payout))
+    }
+
+    /// Callback recording the payout as pending if the transfer failed.
+    fn on_payout_callback(pool_id: PoolId, account_id: AccountId, payout: u128) -> Promise {
+        Self::ext(env::current_account_id())
+            .with_static_gas(Gas::from_gas(5_000_000_000_000))
+            .on_ft_transfer_then_remove(pool_id, account_id, payout)
+    }
+}
+
+/// Transfer the payout to the account.
+fn transfer_payout(token_id: &AccountId, account_id: &AccountId, payout: u128) -> Promise {
+    Promise::new(token_id.clone()).function_call(
+        "ft_transfer".to_string(),
+        serde_json::json!({
+            "receiver_id": account_id,
+            "amount": payout.to_string(),
+        })
+        .to_string()
+        .into_bytes(),
+        NearToken::from_yoctonear(1), // Attach 1 yoctoNEAR
+        Gas::from_gas(20_000_000_000_000),
+    )
+}
@@ -0,0 +1,61 @@
+use near_sdk::{assert_one_yocto, env, near, AccountId};
+
+use crate::{StakingContract, StakingContractExt, UserOperationState, NANOSECONDS};
+
+/// Time after which a non-Idle operation state is considered stuck (1 hour).
+pub const STUCK_STATE_TIMEOUT: u64 = 60 * 60;
+
+#[near]
+impl StakingContract {
+    /// Reset the operation state of an account to Idle (only callable by the owner).
+    /// - `account_id`: Account left in Staking or Unstaking by a failed callback.
+    #[payable]
+    pub fn reset_user_state(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can reset user state."
+        );
+        self.set_user_state(&account_id, UserOperationState::Idle);
+        env::log_str(&format!("User state of {} reset to Idle", account_id));
+    }
+
+    /// Query accounts in a non-Idle state together with the time the state was set.
+    /// `offset` and `limit` paginate over all recorded user states.
+    pub fn get_stuck_accounts(
+        &self,
+        offset: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, UserOperationState, u64)> {
+        let start = offset.unwrap_or(0);
+        let l = limit.unwrap_or(50);
+        self.user_states
+            .iter()
+            .skip(start as usize)
+            .take(l as usize)
+            .filter(|(_, state)| !matches!(state, UserOperationState::Idle))
+            .map(|(account_id, state)| {
+                let since = self.user_state_times.get(&account_id).unwrap_or(0);
+                (account_id, state, since)
+            })
+            .collect()
+    }
+}
+
+impl StakingContract {
+    /// Record the operation state of an account with the current timestamp.
+    pub(crate) fn set_user_state(&mut self, account_id: &AccountId, state: UserOperationState) {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        self.user_states.insert(account_id, &state);
+        self.user_state_times.insert(account_id, &current_time);
+    }
+
+    /// Whether the current state of an account is older than `STUCK_STATE_TIMEOUT`.
+    /// States recorded before timestamps were tracked are treated as stale.
+    pub(crate) fn is_user_state_stale(&self, account_id: &AccountId) -> bool {
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let since = self.user_state_times.get(account_id).unwrap_or(0);
+        current_time >= since + STUCK_STATE_TIMEOUT
+    }
+}
@@ -0,0 +1,242 @@
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, Gas, NearToken, Promise};
+
+use crate::pool::{Pool, PoolId, DEFAULT_POOL_ID};
+use crate::{StakingContract, StakingContractExt, NANOSECONDS, U256};
+
+/// Vesting settings of a pool
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct VestingConfig {
+    cliff: u64,    // Seconds before anything vests
+    duration: u64, // Seconds until everything is vested, counted from the grant
+}
+
+/// Reward granted to an account at one time, vesting on its own schedule
+#[near(serializers = [borsh])]
+pub struct VestingTranche {
+    start: u64,      // Time of the grant
+    cliff: u64,      // Cliff of the pool at the grant
+    duration: u64,   // Duration of the pool at the grant
+    total: u128,     // Amount vesting since `start`
+    withdrawn: u128, // Part of `total` already withdrawn
+}
+
+/// Rewards of an account vesting in a pool
+#[near(serializers = [borsh])]
+pub struct VestingSchedule {
+    tranches: Vec<VestingTranche>, // Grants not fully vested, in grant order
+    unlocked: u128,                // Fully vested grants not yet withdrawn
+}
+
+/// Vesting rewards of an account
+#[near(serializers = [json])]
+pub struct VestingBalance {
+    vested: U128, // Withdrawable with `withdraw_vested`
+    locked: U128, // Still vesting
+}
+
+#[near]
+impl StakingContract {
+    /// Set the vesting of rewards paid by a pool (only callable by the owner).
+    /// - `config`: Cliff and linear unlock duration, `None` pays rewards right away.
+    ///   Rewards already vesting keep the schedule they were granted with.
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn set_vesting_config(&mut self, config: Option<VestingConfig>, pool_id: Option<PoolId>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.owner_id,
+            env::predecessor_account_id(),
+            "Only the owner can set vesting config."
+        );
+        if let Some(config) = &config {
+            require!(config.duration > 0, "Vesting duration should be positive");
+            require!(
+                config.cliff <= config.duration,
+                "Cliff cannot exceed the vesting duration"
+            );
+        }
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        pool.vesting = config;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!("Vesting config of pool {} updated", pool_id));
+    }
+
+    /// Withdraw the vested rewards of a pool
+    /// - `pool_id`: Pool, defaults to the default pool.
+    #[payable]
+    pub fn withdraw_vested(&mut self, pool_id: Option<PoolId>) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let mut pool = self.load_pool(pool_id);
+        require!(!pool.pause_status.claim, "Claim paused");
+        let key = (pool_id, account_id.clone());
+        let mut schedule = self
+            .vesting_schedules
+            .get(&key)
+            .expect("No vesting rewards for this account");
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let amount = schedule.withdrawable_at(current_time);
+        require!(amount > 0, "Nothing vested yet");
+        schedule.unlocked = 0;
+        for tranche in schedule.tranches.iter_mut() {
+            tranche.withdrawn = tranche.vested_at(current_time);
+        }
+        schedule
+            .tranches
+            .retain(|tranche| tranche.withdrawn < tranche.total);
+        if schedule.tranches.is_empty() {
+            self.vesting_schedules.remove(&key);
+        } else {
+            self.vesting_schedules.insert(&key, &schedule);
+        }
+        pool.total_vesting -= amount;
+        self.save_pool(pool_id, &pool);
+        env::log_str(&format!(
+            "Withdraw vested reward of {} to {}",
+            amount, account_id
+        ));
+
+        Promise::new(pool.staked_token)
+            .function_call(
+                "ft_transfer".to_string(),
+                serde_json::json!({
+                    "receiver_id": account_id,
+                    "amount": amount.to_string(),
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_gas(20_000_000_000_000),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(5_000_000_000_000))
+                    .on_withdraw_vested(pool_id, account_id, amount),
+            )
+    }
+
+    /// Callback: After ft_transfer, record the amount as a pending payout if the transfer failed.
+    #[private]
+    pub fn on_withdraw_vested(
+        &mut self,
+        pool_id: PoolId,
+        account_id: AccountId,
+        amount: u128,
+        #[callback_result] call_result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        match call_result {
+            Ok(()) => true,
+            Err(_) => {
+                self.credit_pending_payout(pool_id, &account_id, amount);
+                false
+            }
+        }
+    }
+
+    /// Query the vesting settings of a pool
+    pub fn get_vesting_config(&self, pool_id: Option<PoolId>) -> Option<VestingConfig> {
+        self.load_pool(pool_id.unwrap_or(DEFAULT_POOL_ID)).vesting
+    }
+
+    /// Query the vested and locked rewards of an account in a pool
+    pub fn get_vesting_balance(
+        &self,
+        account_id: AccountId,
+        pool_id: Option<PoolId>,
+    ) -> VestingBalance {
+        let pool_id = pool_id.unwrap_or(DEFAULT_POOL_ID);
+        let Some(schedule) = self.vesting_schedules.get(&(pool_id, account_id)) else {
+            return VestingBalance {
+                vested: U128(0),
+                locked: U128(0),
+            };
+        };
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        VestingBalance {
+            vested: U128(schedule.withdrawable_at(current_time)),
+            locked: U128(schedule.locked_at(current_time)),
+        }
+    }
+}
+
+impl StakingContract {
+    /// Move a paid reward into the vesting schedule of an account as a new tranche vesting
+    /// from now on. Earlier tranches keep their schedule, fully vested ones are merged into
+    /// the withdrawable balance. The caller saves the pool.
+    pub(crate) fn vest_reward(
+        &mut self,
+        pool_id: PoolId,
+        pool: &mut Pool,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        let Some(config) = &pool.vesting else {
+            return;
+        };
+        if amount == 0 {
+            return;
+        }
+        let current_time = env::block_timestamp() / NANOSECONDS;
+        let key = (pool_id, account_id.clone());
+        let mut schedule = self.vesting_schedules.get(&key).unwrap_or(VestingSchedule {
+            tranches: Vec::new(),
+            unlocked: 0,
+        });
+        for tranche in schedule.tranches.iter() {
+            if tranche.vested_at(current_time) == tranche.total {
+                schedule.unlocked += tranche.total - tranche.withdrawn;
+            }
+        }
+        schedule
+            .tranches
+            .retain(|tranche| tranche.vested_at(current_time) < tranche.total);
+        schedule.tranches.push(VestingTranche {
+            start: current_time,
+            cliff: config.cliff,
+            duration: config.duration,
+            total: amount,
+            withdrawn: 0,
+        });
+        self.vesting_schedules.insert(&key, &schedule);
+        pool.total_vesting += amount;
+        env::log_str(&format!("Vest reward of {} for {}", amount, account_id));
+    }
+}
+
+impl VestingSchedule {
+    /// Vested and not yet withdrawn at `time`.
+    fn withdrawable_at(&self, time: u64) -> u128 {
+        self.unlocked
+            + self
+                .tranches
+                .iter()
+                .map(|tranche| tranche.vested_at(time) - tranche.withdrawn)
+                .sum::<u128>()
+    }
+
+    /// Still vesting at `time`.
+    fn locked_at(&self, time: u64) -> u128 {
+        self.tranches
+            .iter()
+            .map(|tranche| tranche.total - tranche.vested_at(time))
+            .sum()
+    }
+}
+
+impl VestingTranche {
+    /// Part of `total` vested at `time`: nothing before the cliff, then linear.
+    fn vested_at(&self, time: u64) -> u128 {
+        let elapsed = time.saturating_sub(self.start);
+        if elapsed < self.cliff {
+            0
+        } else if elapsed >= self.duration {
+            self.total
+        } else {
+            (U256::from(self.total) * U256::from(elapsed) / U256::from(self.duration)).as_u128()
+        }
+    }
+}
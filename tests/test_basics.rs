@@ -46,7 +46,12 @@ async fn test_unstake_cross_contract_failure() -> Result<()> {
 
     let _ = root_account
         .call(staking_contract.id(), "new")
-        .args_json((root_account.id(), token_contract.id(), U128(1_000_000u128)))
+        .args_json((
+            root_account.id(),
+            token_contract.id(),
+            U128(1_000_000u128),
+            None::<u64>,
+        ))
         .transact()
         .await?
         .into_result()?; // Unwrap to catch init failure
@@ -195,3 +200,136 @@ async fn test_unstake_cross_contract_failure() -> Result<()> {
 
     Ok(())
 }
+
+/// Integration test showing that unstake() to a receiver that is not registered on the
+/// token contract is rejected up front and keeps the stake, instead of a silent failed transfer.
+#[tokio::test]
+async fn test_unstake_unregistered_receiver() -> Result<()> {
+    // 1. Spin up a local sandbox network
+    let worker = sandbox().await?;
+
+    // 2. Compile the token and staking contracts to WASM
+    let token_wasm = compile_project("../publicai-token").await?;
+    let staking_wasm = compile_project(".").await?; // current crate
+
+    // 3. Deploy and initialize the token contract
+    let token_contract: Contract = worker.dev_deploy(&token_wasm).await?;
+    let root_account = worker.root_account()?;
+    let metadata = json!({
+        "spec": "ft-1.0.0",
+        "name": "Test Token",
+        "symbol": "TT",
+        "decimals": 18,
+        "icon": null,
+        "reference": null,
+        "reference_hash": null
+    });
+    let _ = root_account
+        .call(token_contract.id(), "new")
+        .args_json(json!({
+            "owner_id": root_account.id(),
+            "total_supply": U128(1_000_000_000u128),
+            "metadata": metadata
+        }))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // 4. Deploy the staking contract
+    let staking_contract: Contract = worker.dev_deploy(&staking_wasm).await?;
+    let _ = root_account
+        .call(staking_contract.id(), "new")
+        .args_json((
+            root_account.id(),
+            token_contract.id(),
+            U128(1_000_000u128),
+            None::<u64>,
+        ))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // 5. Register alice and the staking contract, then fund alice
+    let alice: Account = worker.dev_create_account().await?;
+    for account_id in [alice.id(), staking_contract.id()] {
+        let _ = root_account
+            .call(token_contract.id(), "storage_deposit")
+            .args_json(json!({ "account_id": account_id, "registration_only": null }))
+            .deposit(NearToken::from_yoctonear(
+                1_250_000_000_000_000_000_000_000u128,
+            ))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    let _ = root_account
+        .call(token_contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": alice.id(),
+            "amount": U128(1_000_000u128),
+            "memo": null
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // 6. Alice stakes all her tokens, then unregisters from the token contract
+    let _ = alice
+        .call(token_contract.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": staking_contract.id(),
+            "amount": U128(1_000_000u128),
+            "memo": null,
+            "msg": ""
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+    worker.fast_forward(10).await?;
+
+    let _ = alice
+        .call(token_contract.id(), "storage_unregister")
+        .args_json(json!({ "force": null }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // 7. Unstake is rejected because alice is not registered
+    let unstake_exec = alice
+        .call(staking_contract.id(), "unstake")
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    let unstaked: bool = unstake_exec.json()?;
+    assert!(!unstaked, "Unstake should be rejected");
+    worker.fast_forward(10).await?;
+
+    let stake_info: serde_json::Value = alice
+        .view(staking_contract.id(), "get_stake_info")
+        .args_json(json!({ "account_id": alice.id() }))
+        .await?
+        .json()?;
+    assert!(
+        stake_info != serde_json::Value::Null,
+        "Stake should be kept after a rejected unstake"
+    );
+    let staking_balance: U128 = alice
+        .view(token_contract.id(), "ft_balance_of")
+        .args_json(json!({ "account_id": staking_contract.id() }))
+        .await?
+        .json()?;
+    assert_eq!(
+        staking_balance.0, 1_000_000,
+        "Staking contract should still hold the stake"
+    );
+
+    Ok(())
+}
@@ -52,7 +52,12 @@ async fn test_unstake_assets_drain() -> Result<()> {
 
     let _ = root_account
         .call(staking_contract.id(), "new")
-        .args_json((root_account.id(), token_contract.id(), U128(1u128)))
+        .args_json((
+            root_account.id(),
+            token_contract.id(),
+            U128(1u128),
+            None::<u64>,
+        ))
         .transact()
         .await?
         .into_result()?; // Unwrap to catch init failure